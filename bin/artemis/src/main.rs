@@ -9,30 +9,39 @@ use ethers::providers::{Provider, Ws};
 
 // 套利策略
 use arb::strategy::OpenseaSudoArb;
-use arb::types::{Action, Config, Event};
+use arb::types::{Action, Config, Event, PoolSnapshot};
 
 // 事件收集器
-use arbot_core::collectors::block_collector::BlockCollector;
+use arbot_core::collectors::block_collector::{BlockCollector, BlockEvent};
 // use arbot_core::collectors::opensea_order_collector::OpenseaOrderCollector;
 
 // 执行器
-use arbot_core::executors::mempool_executor::MempoolExecutor;
+use arbot_core::executors::mempool_executor::{MempoolExecutor, SubmitTxToMempool};
+use arbot_core::executors::simulation_executor::SimulationExecutor;
 
 use ethers::signers::{LocalWallet, Signer};
 
 
-use tracing::{info, Level};
+use tracing::{error, info, Level};
 use tracing_subscriber::{filter, prelude::*};
 
+use std::path::PathBuf;
 use std::str::FromStr;
 use std::sync::Arc;
 
-use arbot_core::engine::Engine;
-use arbot_core::types::{CollectorMap, ExecutorMap};
+use arbot_core::collectors::replay_collector::{ReplayCollector, ReplaySpeed};
+use arbot_core::engine::{Engine, RestartPolicy};
+use arbot_core::types::{CollectorMap, Executor, ExecutorMap, Strategy};
+use arbot_core::utilities::event_recorder::EventRecorder;
+use arbot_core::utilities::snapshot_store::SnapshotStore;
 
 use std::env;
+use std::net::SocketAddr;
 use dotenv::dotenv;
 
+/// Number of actions the executor may handle at once.
+const EXECUTOR_CONCURRENCY: usize = 4;
+
 /// CLI Options.
 #[derive(Parser, Debug)]
 pub struct Args {
@@ -55,6 +64,31 @@ pub struct Args {
     /// Percentage of profit to pay in gas.                                 
     #[arg(long)]                                      // 利润的百分比
     pub bid_percentage: u64,
+
+    /// File to which every collected event is appended, as JSON lines.
+    #[arg(long)]                                      // 事件记录文件
+    pub record_events: Option<PathBuf>,
+
+    /// File of previously recorded events to replay instead of live collectors.
+    /// Transactions are always simulated during a replay.
+    #[arg(long)]                                      // 事件回放文件
+    pub replay_events: Option<PathBuf>,
+
+    /// Simulate transactions instead of sending them.
+    #[arg(long)]                                      // 模拟交易, 不发送
+    pub dry_run: bool,
+
+    /// Address on which to serve Prometheus metrics, e.g. 127.0.0.1:9000.
+    #[arg(long)]                                      // 监控指标地址
+    pub metrics_addr: Option<SocketAddr>,
+
+    /// Loopback address on which to serve the control plane, e.g. 127.0.0.1:9001.
+    #[arg(long)]                                      // 控制面地址
+    pub control_addr: Option<SocketAddr>,
+
+    /// Directory in which strategy snapshots are persisted across restarts.
+    #[arg(long)]                                      // 策略快照目录
+    pub snapshot_dir: Option<PathBuf>,
 }
 
 impl Default for Args {
@@ -68,6 +102,12 @@ impl Default for Args {
             bid_percentage: env::var("bid_percentage").unwrap().parse().unwrap(),
             private_key: env::var("private_key").unwrap(),
             arb_contract_address: env::var("arb_contract_address").unwrap(),
+            record_events: env::var("record_events").ok().map(PathBuf::from),
+            replay_events: env::var("replay_events").ok().map(PathBuf::from),
+            dry_run: env::var("dry_run").is_ok_and(|v| v == "true"),
+            metrics_addr: env::var("metrics_addr").ok().map(|addr| addr.parse().unwrap()),
+            control_addr: env::var("control_addr").ok().map(|addr| addr.parse().unwrap()),
+            snapshot_dir: env::var("snapshot_dir").ok().map(PathBuf::from),
         }
     }
 }
@@ -105,30 +145,98 @@ async fn main() -> Result<()> {
     // Set up engine.
     let mut engine: Engine<Event, Action> = Engine::default();
 
-    // Set up block collector.                                                          // 设置块收集器。
-    let block_collector = Box::new(BlockCollector::new(provider.clone()));
-    let block_collector = CollectorMap::new(block_collector, Event::NewBlock);          // 创建块收集器
-    engine.add_collector(Box::new(block_collector));                                    // 添加块收集器
+    // Persist strategy state across restarts.                                          // 保存策略状态
+    let snapshots = args.snapshot_dir.map(SnapshotStore::new);
+    if let Some(store) = &snapshots {
+        engine = engine.with_snapshots(store.clone());
+    }
 
-    // Set up opensea collector.                                                
-    // let opensea_collector = Box::new(OpenseaOrderCollector::new(args.opensea_api_key));
-    // let opensea_collector = CollectorMap::new(opensea_collector, |e| Event::OpenseaOrder(Box::new(e)));
-    // engine.add_collector(Box::new(opensea_collector));
+    // Record events if requested.                                                      // 记录事件
+    if let Some(path) = args.record_events {
+        engine.record_events(EventRecorder::new(path));
+    }
+
+    // Never broadcast transactions built from replayed events.                         // 回放时只模拟交易
+    let replay = args.replay_events.is_some();
+    let dry_run = args.dry_run || replay;
 
     // Set up opensea sudo arb strategy.                                                // 设置 opensea sudo arb 策略
     let config = Config {
         arb_contract_address: H160::from_str(&args.arb_contract_address)?, 
         bid_percentage: args.bid_percentage,
+        replay,
     };
     let strategy = OpenseaSudoArb::new(Arc::new(provider.clone()), opensea_client, config);
-    engine.add_strategy(Box::new(strategy));
 
-    // Set up flashbots executor.                                                       // 设置 flashbots 执行器
-    let executor = Box::new(MempoolExecutor::new(provider.clone()));                    // 创建执行器
+    if let Some(path) = args.replay_events {
+        // Replay recorded events instead of listening to live ones.                    // 回放记录的事件
+        let replay_collector = ReplayCollector::<Event>::new(path, ReplaySpeed::Recorded);
+        engine.add_collector_with_policy(Box::new(replay_collector), RestartPolicy::never())?;
+    } else {
+        // Set up block collector.                                                      // 设置块收集器。
+        let mut block_collector = BlockCollector::new(provider.clone());
+        if let Some(store) = &snapshots {
+            // Resume after the last block the strategy processed before a restart.     // 重启后从策略快照的区块继续
+            if let Some(snapshot) = store.load(strategy.name()).await? {
+                let snapshot: PoolSnapshot = serde_json::from_value(snapshot)?;
+                if snapshot.last_block > 0 {
+                    block_collector = block_collector.with_start_block(snapshot.last_block + 1);
+                }
+            }
+        }
+        let block_collector = Box::new(block_collector);
+        let block_collector = CollectorMap::new(block_collector, |event| match event {  // 创建块收集器
+            BlockEvent::NewBlock(block) => Event::NewBlock(block),                      // 新区块
+            BlockEvent::Reorg(reorg) => Event::Reorg(reorg),                            // 链重组
+        });
+        engine.add_collector(Box::new(block_collector))?;                               // 添加块收集器
+    }
+
+    // Set up opensea collector.                                                
+    // let opensea_collector: Box<dyn Collector<OpenseaEvent>> = Box::new(OpenseaOrderCollector::new(args.opensea_api_key));
+    // let opensea_collector = CollectorMap::new(opensea_collector, Event::Opensea);
+    // engine.add_collector(Box::new(opensea_collector))?;
+
+    // Add opensea sudo arb strategy.                                                   // 添加 opensea sudo arb 策略
+    engine.add_strategy(Box::new(strategy))?;
+
+    // Set up mempool executor, or simulate transactions on a dry run.                 // 设置执行器
+    let executor: Box<dyn Executor<SubmitTxToMempool>> = if dry_run {
+        Box::new(SimulationExecutor::new(provider.clone()))                             // 创建模拟执行器
+    } else {
+        // Transactions share one signer, so send them in nonce order.                  // 同一签名者的交易按顺序发送
+        Box::new(MempoolExecutor::new(provider.clone()).with_receipts(true).with_sender_ordering(true))
+    };
     let executor = ExecutorMap::new(executor, |action| match action {                   // 创建执行器映射
-        Action::SubmitTx(tx) => Some(tx),                                               // 提交交易
+        Action::SubmitTx(arb) => Some(arb.tx),                                          // 提交交易
     });
-    engine.add_executor(Box::new(executor));
+    engine.add_executor_with_concurrency(Box::new(executor), EXECUTOR_CONCURRENCY)?;   // 并发执行动作
+
+    // Feed execution outcomes back to the strategy.                                    // 将执行结果反馈给策略
+    engine.report_executions(|report| Some(Event::ExecutionReport(Box::new(report))));
+
+    // Serve engine metrics.                                                             // 提供监控指标
+    if let Some(addr) = args.metrics_addr {
+        let metrics = engine.metrics();
+        tokio::spawn(async move {
+            if let Err(e) = metrics.serve(addr).await {
+                error!("error serving metrics: {}", e);
+            }
+        });
+    }
+
+    // Serve the control plane.                                                         // 提供控制面
+    if let Some(addr) = args.control_addr {
+        let control = engine.control();
+        tokio::spawn(async move {
+            if let Err(e) = control.serve(addr).await {
+                error!("error serving control plane: {}", e);
+            }
+        });
+    }
+
+    // Shut down gracefully on SIGINT / SIGTERM.                                          // 收到信号时优雅关闭
+    tokio::spawn(engine.shutdown_handle().shutdown_on_signal());
 
     // Start engine.                                                                    // 启动引擎
     if let Ok(mut set) = engine.run().await {                                           
@@ -1,48 +1,497 @@
+use super::transport::{self, BlockSource};
 use crate::types::{Collector, CollectorStream};
-use anyhow::Result;
+use crate::utilities::snapshot_store::SnapshotStore;
+use anyhow::{anyhow, Result};
 use async_trait::async_trait;
 use ethers::{
     prelude::Middleware,
     providers::PubsubClient,
-    types::{H256, U64},
+    types::{Block, Bloom, H256, U256, U64},
 };
-use std::sync::Arc;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use tokio::sync::Mutex as AsyncMutex;
 use tokio_stream::StreamExt;
+use tracing::{error, warn};
+
+/// Number of recent blocks tracked to detect reorgs.
+const DEFAULT_REORG_DEPTH: usize = 64;
+
+/// Minimum time between two writes of a block checkpoint.
+const DEFAULT_CHECKPOINT_INTERVAL: Duration = Duration::from_secs(1);
 
 /// A collector that listens for new blocks, and generates a stream of
-/// [events](NewBlock) which contain the block number and hash.
+/// [events](BlockEvent) which contain the block header. The collector tracks
+/// recent blocks, and emits a [Reorg](Reorg) when the chain switches to a
+/// different branch.
+///
+/// Given a [start block](BlockCollector::with_start_block) or a
+/// [checkpoint](BlockCheckpoint), the collector first emits
+/// the blocks from there up to the chain head, then switches to new blocks
+/// without gaps or duplicates.
+///
+/// The collector subscribes to new blocks, falling back to polling when the
+/// node does not support subscriptions. Use
+/// [polling](BlockCollector::polling) for transports without subscriptions,
+/// such as HTTP.
 pub struct BlockCollector<M> {
     provider: Arc<M>,
+    blocks: BlockSource<M>,
+    max_reorg_depth: usize,
+    start_block: Option<U64>,
+    checkpoint: Option<BlockCheckpoint>,
+    /// Recent blocks of the canonical chain, oldest first. Kept across
+    /// restarts so that reorgs during an outage are detected.
+    recent: Mutex<VecDeque<NewBlock>>,
 }
 
-/// A new block event, containing the block number and hash.
+/// The last block a consumer of a [BlockCollector](BlockCollector) finished
+/// processing, persisted among the [checkpoints](SnapshotStore::checkpoints)
+/// of a store. The consumer [commits](BlockCheckpoint::commit) each block
+/// once processed, and a collector given a clone of the checkpoint resumes
+/// from the block after it once the bot restarts.
 #[derive(Debug, Clone)]
+pub struct BlockCheckpoint {
+    interval: Duration,
+    writer: Arc<AsyncMutex<CheckpointWriter>>,
+}
+
+/// Writes the blocks committed to a [BlockCheckpoint](BlockCheckpoint).
+#[derive(Debug)]
+struct CheckpointWriter {
+    store: SnapshotStore,
+    name: String,
+    /// When the checkpoint was last written.
+    written_at: Option<Instant>,
+    /// The last block committed since then, written once the interval
+    /// elapses or the checkpoint is dropped.
+    pending: Option<NewBlock>,
+}
+
+/// A new block event, containing the block header.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct NewBlock {
     pub hash: H256,
     pub number: U64,
+    #[serde(default)]
+    pub parent_hash: H256,
+    #[serde(default)]
+    pub timestamp: U256,
+    #[serde(default)]
+    pub base_fee_per_gas: Option<U256>,
+    #[serde(default)]
+    pub gas_used: U256,
+    #[serde(default)]
+    pub gas_limit: U256,
+    #[serde(default)]
+    pub logs_bloom: Option<Bloom>,
 }
 
-impl<M> BlockCollector<M> {
+/// A chain reorganisation: `old_blocks` were orphaned and replaced by
+/// `new_blocks`, both ordered by block number.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Reorg {
+    /// Number of orphaned blocks.
+    pub depth: u64,
+    pub old_blocks: Vec<NewBlock>,
+    pub new_blocks: Vec<NewBlock>,
+}
+
+/// An event emitted by a [BlockCollector](BlockCollector). A
+/// [Reorg](BlockEvent::Reorg) is followed by a
+/// [NewBlock](BlockEvent::NewBlock) for each block of the new branch.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum BlockEvent {
+    NewBlock(Box<NewBlock>),
+    Reorg(Reorg),
+}
+
+impl NewBlock {
+    /// Builds the event from a block header, returning `None` for pending
+    /// blocks.
+    pub fn from_block<T>(block: &Block<T>) -> Option<Self> {
+        Some(Self {
+            hash: block.hash?,
+            number: block.number?,
+            parent_hash: block.parent_hash,
+            timestamp: block.timestamp,
+            base_fee_per_gas: block.base_fee_per_gas,
+            gas_used: block.gas_used,
+            gas_limit: block.gas_limit,
+            logs_bloom: block.logs_bloom,
+        })
+    }
+}
+
+impl<M> BlockCollector<M>
+where
+    M: Middleware,
+    M::Provider: PubsubClient,
+    M::Error: 'static,
+{
+    /// Creates a collector subscribing to new blocks.
     pub fn new(provider: Arc<M>) -> Self {
-        Self { provider }
+        Self::with_source(provider, transport::subscribe_blocks)
+    }
+}
+
+impl<M> BlockCollector<M>
+where
+    M: Middleware,
+    M::Error: 'static,
+{
+    /// Creates a collector polling a block filter at the provider's
+    /// [interval](ethers::providers::Provider::interval).
+    pub fn polling(provider: Arc<M>) -> Self {
+        Self::with_source(provider, transport::poll_blocks)
+    }
+}
+
+impl<M> BlockCollector<M> {
+    fn with_source(provider: Arc<M>, blocks: BlockSource<M>) -> Self {
+        Self {
+            provider,
+            blocks,
+            max_reorg_depth: DEFAULT_REORG_DEPTH,
+            start_block: None,
+            checkpoint: None,
+            recent: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    /// Emits the blocks from `block` up to the chain head before streaming
+    /// new blocks.
+    pub fn with_start_block(mut self, block: impl Into<U64>) -> Self {
+        self.start_block = Some(block.into());
+        self
+    }
+
+    /// Resumes from the block after the last block committed to
+    /// `checkpoint`. A checkpoint takes precedence over the start block. The
+    /// checkpointed block is tracked again, so that a reorg while the bot was
+    /// down is reported.
+    pub fn with_checkpoint(mut self, checkpoint: BlockCheckpoint) -> Self {
+        self.checkpoint = Some(checkpoint);
+        self
+    }
+
+    /// Sets how many recent blocks are tracked, which bounds the depth of
+    /// the reorgs that can be detected.
+    pub fn with_max_reorg_depth(mut self, blocks: usize) -> Self {
+        assert!(blocks > 0, "max reorg depth must be non-zero");
+        self.max_reorg_depth = blocks;
+        self
+    }
+
+    /// Appends blocks of the canonical chain, forgetting the oldest ones.
+    fn push_blocks(&self, blocks: impl IntoIterator<Item = NewBlock>) {
+        let mut recent = self.recent.lock().unwrap();
+        recent.extend(blocks);
+        while recent.len() > self.max_reorg_depth {
+            recent.pop_front();
+        }
+    }
+
+    /// Returns how many tracked blocks to keep if the block with the given
+    /// hash is a common ancestor, either a tracked block or the parent of
+    /// the oldest one.
+    fn common_ancestor(&self, hash: H256) -> Option<usize> {
+        let recent = self.recent.lock().unwrap();
+        if recent
+            .front()
+            .is_some_and(|oldest| oldest.parent_hash == hash)
+        {
+            return Some(0);
+        }
+        recent
+            .iter()
+            .position(|block| block.hash == hash)
+            .map(|position| position + 1)
+    }
+
+    /// Returns the first block to emit before streaming new blocks: the
+    /// block after the tracked tip, or after the checkpoint, or the start
+    /// block. Returns `None` if the collector only streams new blocks.
+    async fn resume_from(&self) -> Option<U64> {
+        if self.start_block.is_none() && self.checkpoint.is_none() {
+            return None;
+        }
+        let tip = self.recent.lock().unwrap().back().map(|tip| tip.number);
+        if let Some(tip) = tip {
+            return Some(tip + 1);
+        }
+        let checkpoint = match &self.checkpoint {
+            Some(checkpoint) => checkpoint.load().await,
+            None => None,
+        };
+        if let Some(checkpoint) = checkpoint {
+            let from = checkpoint.number + 1;
+            self.push_blocks([checkpoint]);
+            return Some(from);
+        }
+        self.start_block
+    }
+}
+
+impl BlockCheckpoint {
+    /// Creates a checkpoint persisted under `name` among the
+    /// [checkpoints](SnapshotStore::checkpoints) of `store`.
+    pub fn new(store: &SnapshotStore, name: impl Into<String>) -> Self {
+        Self {
+            interval: DEFAULT_CHECKPOINT_INTERVAL,
+            writer: Arc::new(AsyncMutex::new(CheckpointWriter {
+                store: store.checkpoints(),
+                name: name.into(),
+                written_at: None,
+                pending: None,
+            })),
+        }
+    }
+
+    /// Sets the minimum time between two writes. The last block committed
+    /// in the meantime, such as while backfilling, is written once the
+    /// interval elapses, or when the last clone of the checkpoint is dropped.
+    pub fn with_interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// Records that `block` was processed, writing it unless the checkpoint
+    /// was written less than the interval ago, in which case it is written
+    /// once the interval elapses. Failures are logged.
+    pub async fn commit(&self, block: &NewBlock) {
+        let mut writer = self.writer.lock().await;
+        let elapsed = writer.written_at.map(|at| at.elapsed());
+        match elapsed {
+            Some(elapsed) if elapsed < self.interval => {
+                // A flush is already scheduled if a block is pending.
+                if writer.pending.replace(block.clone()).is_none() {
+                    let writer = Arc::downgrade(&self.writer);
+                    let delay = self.interval - elapsed;
+                    tokio::spawn(async move {
+                        tokio::time::sleep(delay).await;
+                        // Dropping the checkpoint already wrote the block.
+                        if let Some(writer) = writer.upgrade() {
+                            writer.lock().await.flush().await;
+                        }
+                    });
+                }
+            }
+            _ => {
+                writer.pending = Some(block.clone());
+                writer.flush().await;
+            }
+        }
+    }
+
+    /// Writes the last committed block now if its write was deferred by the
+    /// interval. Failures are logged.
+    pub async fn flush(&self) {
+        self.writer.lock().await.flush().await;
+    }
+
+    /// Loads the last written block, if there is one. Failures are logged.
+    pub async fn load(&self) -> Option<NewBlock> {
+        let writer = self.writer.lock().await;
+        let checkpoint = match writer.store.load(&writer.name).await {
+            Ok(checkpoint) => checkpoint?,
+            Err(e) => {
+                warn!("error loading block checkpoint {}: {}", writer.name, e);
+                return None;
+            }
+        };
+        match serde_json::from_value(checkpoint) {
+            Ok(block) => Some(block),
+            Err(e) => {
+                warn!("error parsing block checkpoint {}: {}", writer.name, e);
+                None
+            }
+        }
+    }
+}
+
+impl CheckpointWriter {
+    /// Writes the pending block, if there is one.
+    async fn flush(&mut self) {
+        let Some(block) = self.pending.take() else {
+            return;
+        };
+        self.written_at = Some(Instant::now());
+        let res = match serde_json::to_value(&block) {
+            Ok(checkpoint) => self.store.save(&self.name, &checkpoint).await,
+            Err(e) => Err(e.into()),
+        };
+        if let Err(e) = res {
+            warn!("error saving block checkpoint {}: {}", self.name, e);
+        }
+    }
+}
+
+/// Writes the pending block once the last clone of the checkpoint is
+/// dropped, for example on shutdown, so that a restart resumes after it.
+impl Drop for CheckpointWriter {
+    fn drop(&mut self) {
+        let Some(block) = self.pending.take() else {
+            return;
+        };
+        let res = match serde_json::to_value(&block) {
+            Ok(checkpoint) => self.store.save_blocking(&self.name, &checkpoint),
+            Err(e) => Err(e.into()),
+        };
+        if let Err(e) = res {
+            warn!("error saving block checkpoint {}: {}", self.name, e);
+        }
+    }
+}
+
+impl<M> BlockCollector<M>
+where
+    M: Middleware,
+    M::Error: 'static,
+{
+    /// Links `block` to the tracked chain, returning the events it causes:
+    /// blocks missed since the tracked tip, preceded by a reorg if the tip
+    /// is not an ancestor of `block`.
+    async fn link(&self, block: NewBlock) -> Result<Vec<BlockEvent>> {
+        let tip = self.recent.lock().unwrap().back().map(|tip| tip.number);
+        let Some(tip) = tip else {
+            self.push_blocks([block.clone()]);
+            return Ok(vec![BlockEvent::NewBlock(Box::new(block))]);
+        };
+        if self
+            .recent
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|tracked| tracked.hash == block.hash)
+        {
+            // already part of the tracked chain
+            return Ok(vec![]);
+        }
+        if block.number > tip + self.max_reorg_depth as u64 {
+            warn!(
+                "skipped {} blocks, forgetting tracked blocks",
+                block.number - tip
+            );
+            self.recent.lock().unwrap().clear();
+            self.push_blocks([block.clone()]);
+            return Ok(vec![BlockEvent::NewBlock(Box::new(block))]);
+        }
+
+        // Walk back from the new block until we reach a common ancestor.
+        let mut branch = vec![block];
+        let keep = loop {
+            let oldest = branch.last().unwrap();
+            if let Some(keep) = self.common_ancestor(oldest.parent_hash) {
+                break keep;
+            }
+            if branch.len() > self.max_reorg_depth {
+                warn!("reorg deeper than {} tracked blocks", self.max_reorg_depth);
+                break 0;
+            }
+            let parent = self
+                .provider
+                .get_block(oldest.parent_hash)
+                .await?
+                .and_then(|parent| NewBlock::from_block(&parent))
+                .ok_or_else(|| anyhow!("parent block {:?} not found", oldest.parent_hash))?;
+            branch.push(parent);
+        };
+        branch.reverse();
+
+        let old_blocks: Vec<NewBlock> = self.recent.lock().unwrap().drain(keep..).collect();
+        let mut events = vec![];
+        if !old_blocks.is_empty() {
+            events.push(BlockEvent::Reorg(Reorg {
+                depth: old_blocks.len() as u64,
+                old_blocks,
+                new_blocks: branch.clone(),
+            }));
+        }
+        self.push_blocks(branch.iter().cloned());
+        events.extend(
+            branch
+                .into_iter()
+                .map(|block| BlockEvent::NewBlock(Box::new(block))),
+        );
+        Ok(events)
+    }
+
+    /// Fetches a block of the canonical chain by number.
+    async fn get_block_by_number(&self, number: U64) -> Result<NewBlock> {
+        self.provider
+            .get_block(number)
+            .await?
+            .and_then(|block| NewBlock::from_block(&block))
+            .ok_or_else(|| anyhow!("block {} not found", number))
     }
 }
 
 /// Implementation of the [Collector](Collector) trait for the [BlockCollector](BlockCollector).
-/// This implementation uses the [PubsubClient](PubsubClient) to subscribe to new blocks.
+/// This implementation subscribes to new blocks, or polls for them.
+/// The subscription is opened before backfilling, so that no block falls
+/// between the two; blocks emitted twice are dropped when they are linked.
 #[async_trait]
-impl<M> Collector<NewBlock> for BlockCollector<M>
+impl<M> Collector<BlockEvent> for BlockCollector<M>
 where
     M: Middleware,
-    M::Provider: PubsubClient,
     M::Error: 'static,
 {
-    async fn get_event_stream(&self) -> Result<CollectorStream<NewBlock>> {
-        let stream = self.provider.subscribe_blocks().await?;
-        let stream = stream.filter_map(|block| match block.hash {
-            Some(hash) => block.number.map(|number| NewBlock { hash, number }),
-            None => None,
-        });
+    async fn get_event_stream(&self) -> Result<CollectorStream<BlockEvent>> {
+        let mut blocks = (self.blocks)(&self.provider).await?;
+        let from = self.resume_from().await;
+        let stream = async_stream::stream! {
+            if let Some(from) = from {
+                let head = match self.provider.get_block_number().await {
+                    Ok(head) => head,
+                    Err(e) => {
+                        error!("error fetching chain head: {}", e);
+                        return;
+                    }
+                };
+                let mut number = from;
+                while number <= head {
+                    let linked = match self.get_block_by_number(number).await {
+                        Ok(block) => self.link(block).await,
+                        Err(e) => Err(e),
+                    };
+                    match linked {
+                        Ok(events) => {
+                            for event in events {
+                                yield event;
+                            }
+                        }
+                        Err(e) => {
+                            // let the engine restart the stream, resuming from the tracked tip
+                            error!("error backfilling block {}: {}", number, e);
+                            return;
+                        }
+                    }
+                    number += U64::one();
+                }
+            }
+            while let Some(block) = blocks.next().await {
+                let Some(block) = NewBlock::from_block(&block) else {
+                    continue;
+                };
+                match self.link(block).await {
+                    Ok(events) => {
+                        for event in events {
+                            yield event;
+                        }
+                    }
+                    Err(e) => {
+                        // let the engine restart the stream
+                        error!("error linking new block: {}", e);
+                        return;
+                    }
+                }
+            }
+        };
         Ok(Box::pin(stream))
     }
 }
@@ -0,0 +1,97 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::types::{Collector, CollectorStream};
+use anyhow::Result;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use tokio::time::{interval_at, sleep, Instant, MissedTickBehavior};
+
+/// A collector that emits [Tick](Tick) events on a schedule, for strategies
+/// that need to do periodic housekeeping such as expiring stale listings or
+/// re-quoting pools.
+pub struct IntervalCollector {
+    schedule: Schedule,
+}
+
+/// When an [IntervalCollector](IntervalCollector) emits ticks.
+#[derive(Debug, Clone, Copy)]
+pub enum Schedule {
+    /// Tick every period, starting one period after the stream is created.
+    Fixed(Duration),
+    /// Tick whenever the wall clock reaches a multiple of the period since
+    /// the Unix epoch, for example on every minute.
+    WallClock(Duration),
+}
+
+/// A tick event, emitted by an [IntervalCollector](IntervalCollector).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Tick {
+    /// Number of ticks emitted before this one since the stream started.
+    pub sequence: u64,
+    /// Time at which the tick was scheduled.
+    pub scheduled_at: SystemTime,
+}
+
+impl IntervalCollector {
+    pub fn new(schedule: Schedule) -> Self {
+        let (Schedule::Fixed(period) | Schedule::WallClock(period)) = schedule;
+        assert!(!period.is_zero(), "tick period must be non-zero");
+        Self { schedule }
+    }
+
+    /// Creates a collector ticking every `period`.
+    pub fn every(period: Duration) -> Self {
+        Self::new(Schedule::Fixed(period))
+    }
+
+    /// Creates a collector ticking on wall-clock multiples of `period`.
+    pub fn aligned(period: Duration) -> Self {
+        Self::new(Schedule::WallClock(period))
+    }
+}
+
+/// Returns the first multiple of `period` since the Unix epoch after `now`.
+fn next_aligned(now: SystemTime, period: Duration) -> SystemTime {
+    let since_epoch = now
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos();
+    let period = period.as_nanos();
+    let next = (since_epoch / period + 1) * period;
+    UNIX_EPOCH + Duration::from_nanos(next as u64)
+}
+
+/// Implementation of the [Collector](Collector) trait for the [IntervalCollector](IntervalCollector).
+/// Ticks missed because the stream was not polled in time are skipped.
+#[async_trait]
+impl Collector<Tick> for IntervalCollector {
+    async fn get_event_stream(&self) -> Result<CollectorStream<Tick>> {
+        let stream: CollectorStream<Tick> = match self.schedule {
+            Schedule::Fixed(period) => Box::pin(async_stream::stream! {
+                // Deadlines are converted to wall-clock time relative to the start.
+                let (start, started_at) = (Instant::now(), SystemTime::now());
+                let mut interval = interval_at(start + period, period);
+                interval.set_missed_tick_behavior(MissedTickBehavior::Skip);
+                for sequence in 0.. {
+                    let deadline = interval.tick().await;
+                    yield Tick {
+                        sequence,
+                        scheduled_at: started_at + deadline.duration_since(start),
+                    };
+                }
+            }),
+            Schedule::WallClock(period) => Box::pin(async_stream::stream! {
+                for sequence in 0.. {
+                    let now = SystemTime::now();
+                    let scheduled_at = next_aligned(now, period);
+                    sleep(scheduled_at.duration_since(now).unwrap_or_default()).await;
+                    yield Tick {
+                        sequence,
+                        scheduled_at,
+                    };
+                }
+            }),
+        };
+        Ok(stream)
+    }
+}
@@ -0,0 +1,480 @@
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    marker::PhantomData,
+    sync::{Arc, Mutex, RwLock},
+};
+
+use crate::types::{short_type_name, Collector, CollectorStream};
+use anyhow::Result;
+use async_trait::async_trait;
+use ethers::{
+    contract::{EthEvent, EthLogDecode, LogMeta},
+    prelude::Middleware,
+    providers::PubsubClient,
+    types::{Address, Filter, Log, H256, U256, U64},
+};
+use serde::{Deserialize, Serialize};
+use tokio::sync::watch;
+use tokio_stream::StreamExt;
+use tracing::{error, warn};
+
+/// Number of blocks requested per `eth_getLogs` call while backfilling.
+const DEFAULT_BACKFILL_CHUNK_SIZE: u64 = 2_000;
+
+/// Number of recent logs remembered to de-duplicate logs.
+const DEFAULT_DEDUP_CAPACITY: usize = 10_000;
+
+/// A collector that listens for contract logs matching a [Filter](Filter),
+/// and generates a stream of [logs](Log). The collector can backfill logs
+/// from a given block before switching to live mode, and the set of
+/// contract addresses it listens to can be updated at runtime through
+/// [LogAddresses](LogAddresses).
+///
+/// Use [decoded](LogCollector::decoded) to turn the logs into typed events.
+pub struct LogCollector<M> {
+    provider: Arc<M>,
+    filter: Filter,
+    addresses: Option<LogAddresses>,
+    backfill_from: Option<U64>,
+    backfill_chunk_size: u64,
+    /// Logs emitted recently, used to resume without duplicates when the
+    /// stream is restarted or resubscribed.
+    emitted: Arc<Mutex<EmittedLogs>>,
+}
+
+/// Identifies a log on a given branch of the chain.
+type LogKey = (H256, H256, U256);
+
+/// The logs recently emitted by a [LogCollector](LogCollector), and the
+/// highest block they were emitted up to.
+#[derive(Debug)]
+struct EmittedLogs {
+    seen: HashSet<LogKey>,
+    order: VecDeque<LogKey>,
+    capacity: usize,
+    last_block: Option<U64>,
+}
+
+/// A set of contract addresses shared between a [LogCollector](LogCollector)
+/// and the code updating it, for example a strategy that discovers a new
+/// pool. Handles are cheap to clone, and every update makes the collector
+/// resubscribe with the new set.
+#[derive(Debug, Clone)]
+pub struct LogAddresses {
+    addresses: Arc<RwLock<HashSet<Address>>>,
+    /// Blocks from which to backfill addresses added with
+    /// [insert_from](LogAddresses::insert_from).
+    backfills: Arc<Mutex<HashMap<Address, U64>>>,
+    changed: Arc<watch::Sender<()>>,
+}
+
+/// A log decoded into a typed event, emitted by a
+/// [DecodedLogCollector](DecodedLogCollector).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DecodedLog<T> {
+    pub event: T,
+    pub meta: LogMeta,
+    /// Whether the log was removed by a chain reorganisation.
+    pub removed: bool,
+}
+
+/// A [LogCollector](LogCollector) that decodes logs into events of type `T`,
+/// skipping logs that do not decode.
+pub struct DecodedLogCollector<M, T> {
+    inner: LogCollector<M>,
+    _event: PhantomData<fn() -> T>,
+}
+
+impl<M> LogCollector<M> {
+    pub fn new(provider: Arc<M>, filter: Filter) -> Self {
+        Self {
+            provider,
+            filter,
+            addresses: None,
+            backfill_from: None,
+            backfill_chunk_size: DEFAULT_BACKFILL_CHUNK_SIZE,
+            emitted: Arc::new(Mutex::new(EmittedLogs::new(DEFAULT_DEDUP_CAPACITY))),
+        }
+    }
+
+    /// Listens to the given addresses instead of the filter's own. The
+    /// collector listens to nothing while the set is empty.
+    pub fn with_addresses(mut self, addresses: LogAddresses) -> Self {
+        self.addresses = Some(addresses);
+        self
+    }
+
+    /// Backfills logs from `block` up to the chain head before streaming
+    /// live logs.
+    pub fn with_backfill_from(mut self, block: impl Into<U64>) -> Self {
+        self.backfill_from = Some(block.into());
+        self
+    }
+
+    /// Sets the number of blocks requested per `eth_getLogs` call while
+    /// backfilling, to stay within the provider's range limit.
+    pub fn with_backfill_chunk_size(mut self, blocks: u64) -> Self {
+        assert!(blocks > 0, "backfill chunk size must be non-zero");
+        self.backfill_chunk_size = blocks;
+        self
+    }
+
+    /// Sets how many recent logs are remembered. A copy arriving after its
+    /// log was forgotten, for example when backfilling far behind after a
+    /// restart, is emitted again.
+    pub fn with_dedup_capacity(self, dedup_capacity: usize) -> Self {
+        *self.emitted.lock().unwrap() = EmittedLogs::new(dedup_capacity.max(1));
+        self
+    }
+
+    /// Decodes the logs into events of type `T`. The filter is narrowed to
+    /// the event's signature unless it already sets a first topic.
+    pub fn decoded<T: EthEvent>(mut self) -> DecodedLogCollector<M, T> {
+        if self.filter.topics[0].is_none() {
+            self.filter = self.filter.topic0(T::signature());
+        }
+        self.decoded_events()
+    }
+
+    /// Decodes the logs into events of type `T`, such as the `Events` enum
+    /// of a contract binding, leaving the filter's topics as they are.
+    pub fn decoded_events<T: EthLogDecode>(self) -> DecodedLogCollector<M, T> {
+        DecodedLogCollector {
+            inner: self,
+            _event: PhantomData,
+        }
+    }
+
+    /// Returns the filter to subscribe with, or `None` if the collector
+    /// listens to an empty address set.
+    fn current_filter(&self) -> Option<Filter> {
+        match &self.addresses {
+            Some(addresses) => {
+                let addresses = addresses.to_vec();
+                (!addresses.is_empty()).then(|| self.filter.clone().address(addresses))
+            }
+            None => Some(self.filter.clone()),
+        }
+    }
+
+    /// Returns the block to resume from after the stream restarts.
+    fn resume_block(&self) -> Option<U64> {
+        self.emitted.lock().unwrap().last_block
+    }
+
+    /// Records `log` as emitted, returning false if it was already emitted.
+    fn mark_emitted(&self, log: &Log) -> bool {
+        self.emitted.lock().unwrap().mark(log)
+    }
+}
+
+impl EmittedLogs {
+    fn new(capacity: usize) -> Self {
+        Self {
+            seen: HashSet::new(),
+            order: VecDeque::new(),
+            capacity,
+            last_block: None,
+        }
+    }
+
+    /// Records `log`, returning false if it was already emitted. A removed
+    /// log is forgotten and moves the resume block back before it, so that
+    /// the log is emitted again if its block returns to the chain. Pending
+    /// logs always pass.
+    fn mark(&mut self, log: &Log) -> bool {
+        let (Some(block_hash), Some(tx_hash), Some(index), Some(block)) = (
+            log.block_hash,
+            log.transaction_hash,
+            log.log_index,
+            log.block_number,
+        ) else {
+            return true;
+        };
+        let key = (block_hash, tx_hash, index);
+        if log.removed == Some(true) {
+            if self.seen.remove(&key) {
+                self.order.retain(|seen| *seen != key);
+            }
+            if self.last_block.is_some_and(|last| last >= block) {
+                self.last_block = block.checked_sub(1.into());
+            }
+            return true;
+        }
+        if !self.seen.insert(key) {
+            return false;
+        }
+        self.order.push_back(key);
+        if self.order.len() > self.capacity {
+            if let Some(oldest) = self.order.pop_front() {
+                self.seen.remove(&oldest);
+            }
+        }
+        self.last_block = self.last_block.max(Some(block));
+        true
+    }
+}
+
+impl<M> LogCollector<M>
+where
+    M: Middleware,
+    M::Provider: PubsubClient,
+    M::Error: 'static,
+{
+    /// Fetches the logs matching `filter` from `from` up to the chain head.
+    async fn backfill(&self, filter: &Filter, from: U64) -> Result<Vec<Log>> {
+        let head = self.provider.get_block_number().await?;
+        let mut logs = vec![];
+        let mut start = from;
+        while start <= head {
+            let end = (start + self.backfill_chunk_size - 1).min(head);
+            let chunk = filter.clone().from_block(start).to_block(end);
+            logs.extend(self.provider.get_logs(&chunk).await?);
+            start = end + 1;
+        }
+        Ok(logs)
+    }
+
+    /// Returns the filters and blocks from which to backfill the addresses
+    /// added since the previous subscription to `subscribed`: from the block
+    /// given to [insert_from](LogAddresses::insert_from), or else from the
+    /// chain head, which covers the logs emitted while resubscribing.
+    async fn added_address_backfills(
+        &self,
+        filter: &Filter,
+        subscribed: Option<&HashSet<Address>>,
+    ) -> Result<Vec<(Filter, U64)>> {
+        let Some(addresses) = &self.addresses else {
+            return Ok(vec![]);
+        };
+        let mut backfills = vec![];
+        let mut head = None;
+        for address in addresses.to_vec() {
+            let from = match (addresses.take_backfill(&address), subscribed) {
+                (Some(from), _) => from,
+                (None, Some(subscribed)) if !subscribed.contains(&address) => match head {
+                    Some(head) => head,
+                    None => *head.insert(self.provider.get_block_number().await?),
+                },
+                // already subscribed, or covered by the initial backfill
+                (None, _) => continue,
+            };
+            backfills.push((filter.clone().address(address), from));
+        }
+        Ok(backfills)
+    }
+
+    fn log_stream(&self) -> CollectorStream<'_, Log> {
+        let stream = async_stream::stream! {
+            let mut changes = self.addresses.as_ref().map(LogAddresses::subscribe);
+            // Resume from the last emitted log after a restart, otherwise
+            // from the configured start block.
+            let mut resume_from = self.resume_block().or(self.backfill_from);
+            // Addresses of the previous subscription, if any.
+            let mut subscribed: Option<HashSet<Address>> = None;
+            loop {
+                let Some(filter) = self.current_filter() else {
+                    if !wait_for_change(&mut changes).await {
+                        return;
+                    }
+                    continue;
+                };
+                // Subscribe before backfilling so that no log falls between
+                // the two; duplicates are dropped.
+                let mut live = match self.provider.subscribe_logs(&filter).await {
+                    Ok(live) => live,
+                    Err(e) => {
+                        error!("error subscribing to logs: {}", e);
+                        return;
+                    }
+                };
+                let mut backfills = match self.added_address_backfills(&filter, subscribed.as_ref()).await {
+                    Ok(backfills) => backfills,
+                    Err(e) => {
+                        error!("error backfilling logs: {}", e);
+                        return;
+                    }
+                };
+                if let Some(from) = resume_from.take() {
+                    backfills.insert(0, (filter.clone(), from));
+                }
+                subscribed = self.addresses.as_ref().map(|addresses| addresses.to_vec().into_iter().collect());
+                for (filter, from) in backfills {
+                    match self.backfill(&filter, from).await {
+                        Ok(logs) => {
+                            for log in logs {
+                                if self.mark_emitted(&log) {
+                                    yield log;
+                                }
+                            }
+                        }
+                        Err(e) => {
+                            error!("error backfilling logs: {}", e);
+                            return;
+                        }
+                    }
+                }
+                loop {
+                    let next = tokio::select! {
+                        log = live.next() => Some(log),
+                        changed = wait_for_change(&mut changes) => {
+                            if !changed {
+                                return;
+                            }
+                            None
+                        }
+                    };
+                    match next {
+                        Some(Some(log)) => {
+                            if self.mark_emitted(&log) {
+                                yield log;
+                            }
+                        }
+                        // the subscription ended, let the engine restart us
+                        Some(None) => return,
+                        // the addresses changed: resubscribe, and backfill
+                        // the added addresses
+                        None => break,
+                    }
+                }
+            }
+        };
+        Box::pin(stream)
+    }
+}
+
+impl<M, T> DecodedLogCollector<M, T> {
+    /// Returns the underlying log collector.
+    pub fn into_inner(self) -> LogCollector<M> {
+        self.inner
+    }
+}
+
+/// Waits until the address set changes. Returns false if it never will.
+async fn wait_for_change(changes: &mut Option<watch::Receiver<()>>) -> bool {
+    match changes {
+        Some(changes) => changes.changed().await.is_ok(),
+        None => std::future::pending().await,
+    }
+}
+
+/// Implementation of the [Collector](Collector) trait for the [LogCollector](LogCollector).
+/// This implementation uses the [PubsubClient](PubsubClient) to subscribe to logs.
+#[async_trait]
+impl<M> Collector<Log> for LogCollector<M>
+where
+    M: Middleware,
+    M::Provider: PubsubClient,
+    M::Error: 'static,
+{
+    async fn get_event_stream(&self) -> Result<CollectorStream<Log>> {
+        Ok(self.log_stream())
+    }
+}
+
+/// Implementation of the [Collector](Collector) trait for the [DecodedLogCollector](DecodedLogCollector).
+#[async_trait]
+impl<M, T> Collector<DecodedLog<T>> for DecodedLogCollector<M, T>
+where
+    M: Middleware,
+    M::Provider: PubsubClient,
+    M::Error: 'static,
+    T: EthLogDecode + Send + Sync + 'static,
+{
+    async fn get_event_stream(&self) -> Result<CollectorStream<DecodedLog<T>>> {
+        let stream = self.inner.log_stream().filter_map(|log| {
+            // pending logs carry no metadata
+            if log.block_number.is_none()
+                || log.block_hash.is_none()
+                || log.transaction_hash.is_none()
+                || log.transaction_index.is_none()
+                || log.log_index.is_none()
+            {
+                return None;
+            }
+            match T::decode_log(&log.clone().into()) {
+                Ok(event) => Some(DecodedLog {
+                    event,
+                    meta: LogMeta::from(&log),
+                    removed: log.removed == Some(true),
+                }),
+                Err(e) => {
+                    warn!(
+                        "skipping log that does not decode as {}: {}",
+                        short_type_name::<T>(),
+                        e
+                    );
+                    None
+                }
+            }
+        });
+        Ok(Box::pin(stream))
+    }
+}
+
+impl LogAddresses {
+    pub fn new(addresses: impl IntoIterator<Item = Address>) -> Self {
+        let (changed, _) = watch::channel(());
+        Self {
+            addresses: Arc::new(RwLock::new(addresses.into_iter().collect())),
+            backfills: Arc::new(Mutex::new(HashMap::new())),
+            changed: Arc::new(changed),
+        }
+    }
+
+    /// Adds an address, returning false if it was already present. Logs of
+    /// the address are collected from the chain head once the collector
+    /// resubscribes.
+    pub fn insert(&self, address: Address) -> bool {
+        let inserted = self.addresses.write().unwrap().insert(address);
+        if inserted {
+            self.changed.send_replace(());
+        }
+        inserted
+    }
+
+    /// Adds an address and backfills its logs from `block`, for example the
+    /// block in which a new pool was created. Returns false if the address
+    /// was already present, in which case its logs are still backfilled.
+    pub fn insert_from(&self, address: Address, block: impl Into<U64>) -> bool {
+        self.backfills.lock().unwrap().insert(address, block.into());
+        let inserted = self.addresses.write().unwrap().insert(address);
+        self.changed.send_replace(());
+        inserted
+    }
+
+    /// Removes an address, returning false if it was not present.
+    pub fn remove(&self, address: &Address) -> bool {
+        self.backfills.lock().unwrap().remove(address);
+        let removed = self.addresses.write().unwrap().remove(address);
+        if removed {
+            self.changed.send_replace(());
+        }
+        removed
+    }
+
+    /// Returns whether the set contains `address`.
+    pub fn contains(&self, address: &Address) -> bool {
+        self.addresses.read().unwrap().contains(address)
+    }
+
+    /// Returns the addresses in the set.
+    pub fn to_vec(&self) -> Vec<Address> {
+        self.addresses.read().unwrap().iter().copied().collect()
+    }
+
+    /// Takes the block from which to backfill `address`, if one was given.
+    fn take_backfill(&self, address: &Address) -> Option<U64> {
+        self.backfills.lock().unwrap().remove(address)
+    }
+
+    fn subscribe(&self) -> watch::Receiver<()> {
+        self.changed.subscribe()
+    }
+}
+
+impl Default for LogAddresses {
+    fn default() -> Self {
+        Self::new([])
+    }
+}
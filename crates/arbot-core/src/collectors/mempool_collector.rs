@@ -1,37 +1,228 @@
 use async_trait::async_trait;
 
-use ethers::{prelude::Middleware, providers::PubsubClient, types::Transaction};
+use ethers::{
+    abi::AbiDecode,
+    prelude::Middleware,
+    providers::{PubsubClient, TransactionStream},
+    types::{Address, Selector, Transaction, U256},
+};
 use futures::StreamExt;
-use std::sync::Arc;
+use serde::{Deserialize, Serialize};
+use std::{collections::HashSet, marker::PhantomData, sync::Arc};
 
+use super::transport::{self, PendingTxSource};
 use crate::types::{Collector, CollectorStream};
 use anyhow::Result;
 
 /// A collector that listens for new transactions in the mempool, and generates a stream of
-/// [events](Transaction) which contain the transaction.
+/// [events](Transaction) which contain the transaction. Transactions can be
+/// narrowed down with a [MempoolFilter](MempoolFilter).
+///
+/// Use [decoded](MempoolCollector::decoded) to turn the transactions into typed calls.
+///
+/// The collector subscribes to pending transactions, falling back to polling
+/// when the node does not support subscriptions. Use
+/// [polling](MempoolCollector::polling) for transports without
+/// subscriptions, such as HTTP.
 pub struct MempoolCollector<M> {
     provider: Arc<M>,
+    hashes: PendingTxSource<M>,
+    filter: MempoolFilter,
 }
 
-impl<M> MempoolCollector<M> {
+/// Filters applied to pending transactions by a
+/// [MempoolCollector](MempoolCollector). An empty filter matches every
+/// transaction.
+#[derive(Debug, Clone, Default)]
+pub struct MempoolFilter {
+    to: Option<HashSet<Address>>,
+    selectors: Option<HashSet<Selector>>,
+    min_value: U256,
+    allowed_senders: Option<HashSet<Address>>,
+    denied_senders: HashSet<Address>,
+}
+
+/// A pending transaction decoded into a typed call, emitted by a
+/// [DecodedMempoolCollector](DecodedMempoolCollector).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DecodedPendingTx<C> {
+    pub tx: Transaction,
+    pub call: C,
+}
+
+/// A [MempoolCollector](MempoolCollector) that decodes transactions into
+/// calls of type `C`, such as the `Calls` enum of a contract binding,
+/// skipping transactions that do not decode.
+pub struct DecodedMempoolCollector<M, C> {
+    inner: MempoolCollector<M>,
+    _call: PhantomData<fn() -> C>,
+}
+
+impl<M> MempoolCollector<M>
+where
+    M: Middleware,
+    M::Provider: PubsubClient,
+    M::Error: 'static,
+{
+    /// Creates a collector subscribing to pending transactions.
     pub fn new(provider: Arc<M>) -> Self {
-        Self { provider }
+        Self::with_source(provider, transport::subscribe_pending_txs)
+    }
+}
+
+impl<M> MempoolCollector<M>
+where
+    M: Middleware,
+    M::Error: 'static,
+{
+    /// Creates a collector polling a pending transaction filter at the
+    /// provider's [interval](ethers::providers::Provider::interval).
+    pub fn polling(provider: Arc<M>) -> Self {
+        Self::with_source(provider, transport::poll_pending_txs)
+    }
+}
+
+impl<M> MempoolCollector<M> {
+    fn with_source(provider: Arc<M>, hashes: PendingTxSource<M>) -> Self {
+        Self {
+            provider,
+            hashes,
+            filter: MempoolFilter::default(),
+        }
+    }
+
+    /// Only emits transactions matching `filter`.
+    pub fn with_filter(mut self, filter: MempoolFilter) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    /// Decodes the transactions into calls of type `C`.
+    pub fn decoded<C: AbiDecode>(self) -> DecodedMempoolCollector<M, C> {
+        DecodedMempoolCollector {
+            inner: self,
+            _call: PhantomData,
+        }
+    }
+}
+
+impl<M> MempoolCollector<M>
+where
+    M: Middleware,
+    M::Error: 'static,
+{
+    async fn transaction_stream(&self) -> Result<CollectorStream<'_, Transaction>> {
+        let hashes = (self.hashes)(&self.provider).await?;
+        let stream = TransactionStream::new(self.provider.provider(), hashes, 256);
+        let filter = self.filter.clone();
+        let stream = stream.filter_map(move |res| {
+            let tx = res.ok().filter(|tx| filter.matches(tx));
+            async move { tx }
+        });
+        Ok(Box::pin(stream))
     }
 }
 
 /// Implementation of the [Collector](Collector) trait for the [MempoolCollector](MempoolCollector).
-/// This implementation uses the [PubsubClient](PubsubClient) to subscribe to new transactions.
+/// This implementation subscribes to new transactions, or polls for them.
 #[async_trait]
 impl<M> Collector<Transaction> for MempoolCollector<M>
 where
     M: Middleware,
-    M::Provider: PubsubClient,
     M::Error: 'static,
 {
     async fn get_event_stream(&self) -> Result<CollectorStream<Transaction>> {
-        let stream = self.provider.subscribe_pending_txs().await?;
-        let stream = stream.transactions_unordered(256);
-        let stream = stream.filter_map(|res| async move { res.ok() });
+        self.transaction_stream().await
+    }
+}
+
+/// Implementation of the [Collector](Collector) trait for the [DecodedMempoolCollector](DecodedMempoolCollector).
+#[async_trait]
+impl<M, C> Collector<DecodedPendingTx<C>> for DecodedMempoolCollector<M, C>
+where
+    M: Middleware,
+    M::Error: 'static,
+    C: AbiDecode + Send + Sync + 'static,
+{
+    async fn get_event_stream(&self) -> Result<CollectorStream<DecodedPendingTx<C>>> {
+        let stream = self.inner.transaction_stream().await?;
+        let stream = stream.filter_map(|tx| async move { DecodedPendingTx::decode(tx) });
         Ok(Box::pin(stream))
     }
 }
+
+impl<C: AbiDecode> DecodedPendingTx<C> {
+    /// Decodes the transaction's input into a call of type `C`, returning
+    /// `None` if it does not decode.
+    pub fn decode(tx: Transaction) -> Option<Self> {
+        let call = C::decode(&tx.input).ok()?;
+        Some(Self { tx, call })
+    }
+}
+
+impl MempoolFilter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Only matches transactions sent to one of the given addresses.
+    pub fn to(mut self, addresses: impl IntoIterator<Item = Address>) -> Self {
+        self.to.get_or_insert_with(HashSet::new).extend(addresses);
+        self
+    }
+
+    /// Only matches transactions calling one of the given function selectors.
+    pub fn selectors(mut self, selectors: impl IntoIterator<Item = Selector>) -> Self {
+        self.selectors
+            .get_or_insert_with(HashSet::new)
+            .extend(selectors);
+        self
+    }
+
+    /// Only matches transactions sending at least `value` wei.
+    pub fn min_value(mut self, value: impl Into<U256>) -> Self {
+        self.min_value = value.into();
+        self
+    }
+
+    /// Only matches transactions sent by one of the given addresses.
+    pub fn allow_senders(mut self, senders: impl IntoIterator<Item = Address>) -> Self {
+        self.allowed_senders
+            .get_or_insert_with(HashSet::new)
+            .extend(senders);
+        self
+    }
+
+    /// Never matches transactions sent by the given addresses.
+    pub fn deny_senders(mut self, senders: impl IntoIterator<Item = Address>) -> Self {
+        self.denied_senders.extend(senders);
+        self
+    }
+
+    /// Returns whether `tx` passes every filter.
+    pub fn matches(&self, tx: &Transaction) -> bool {
+        if let Some(to) = &self.to {
+            if !tx.to.is_some_and(|address| to.contains(&address)) {
+                return false;
+            }
+        }
+        if let Some(selectors) = &self.selectors {
+            let selector = tx
+                .input
+                .get(..4)
+                .and_then(|selector| Selector::try_from(selector).ok());
+            if !selector.is_some_and(|selector| selectors.contains(&selector)) {
+                return false;
+            }
+        }
+        if tx.value < self.min_value {
+            return false;
+        }
+        if let Some(allowed) = &self.allowed_senders {
+            if !allowed.contains(&tx.from) {
+                return false;
+            }
+        }
+        !self.denied_senders.contains(&tx.from)
+    }
+}
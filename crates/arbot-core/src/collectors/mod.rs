@@ -5,8 +5,26 @@
 /// This collector listens to a stream of new blocks.
 pub mod block_collector;
 
+/// This collector emits ticks on a fixed or wall-clock aligned schedule.
+pub mod interval_collector;
+
+/// This collector listens to a stream of contract logs.
+pub mod log_collector;
+
 /// This collector listens to a stream of new pending transactions.
 pub mod mempool_collector;
 
 /// This collector listens to a stream of new Opensea orders.
 pub mod opensea_order_collector;
+
+/// This collector races the same events from several providers.
+pub mod racing_collector;
+
+/// This collector listens to a stream of Seaport order events.
+pub mod seaport_event_collector;
+
+/// This collector replays events previously recorded by the engine.
+pub mod replay_collector;
+
+/// How the block and mempool collectors subscribe to or poll their provider.
+mod transport;
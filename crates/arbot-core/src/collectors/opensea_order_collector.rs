@@ -1,52 +1,377 @@
+use std::{
+    collections::HashSet,
+    time::{Duration, SystemTime},
+};
+
 use crate::types::{Collector, CollectorStream};
+use crate::utilities::phoenix_socket::PhoenixSocket;
 use anyhow::Result;
 use async_trait::async_trait;
 use opensea_stream::{
-    client,
-    schema::{self, ItemListedData},
-    subscribe_to, Collection, Network,
+    schema::{
+        self, CollectionOfferData, ItemCancelledData, ItemListedData, ItemReceivedOfferData,
+        ItemSoldData, ItemTransferredData,
+    },
+    Collection, Network,
 };
-use tokio_stream::wrappers::BroadcastStream;
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use tokio::time::{interval_at, sleep, timeout, Instant};
 use tokio_stream::StreamExt;
+use tracing::{debug, warn};
+
+/// Websocket endpoint of the OpenSea stream on mainnet.
+const MAINNET_STREAM_URL: &str = "wss://stream.openseabeta.com/socket/websocket";
+
+/// Websocket endpoint of the OpenSea stream on testnets.
+const TESTNET_STREAM_URL: &str = "wss://testnets-stream.openseabeta.com/socket/websocket";
+
+/// Interval between heartbeats. A socket that has not replied to a heartbeat
+/// by the time the next one is due is considered dead.
+const DEFAULT_HEARTBEAT_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Delay before the first reconnection attempt, doubled after each failure.
+const DEFAULT_RECONNECT_DELAY: Duration = Duration::from_secs(1);
+
+/// Maximum delay between reconnection attempts.
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(30);
 
 /// A collector that listens for new orders on OpenSea, and generates a stream of
-/// [events](OpenseaOrder) which contain the order.
-#[derive(Default)]
+/// [events](OpenseaEvent) which contain the order. The collector can also
+/// surface sales, cancellations, transfers and offers.
+///
+/// The collector sends heartbeats to detect a dead socket, and resubscribes
+/// automatically, reporting the time it was disconnected as a
+/// [StreamGap](StreamGap).
 pub struct OpenseaOrderCollector {
     api_key: String,
+    network: Network,
+    collections: Vec<Collection>,
+    kinds: HashSet<OpenseaEventKind>,
+    url: Option<String>,
+    heartbeat_interval: Duration,
+    reconnect_delay: Duration,
+}
+
+/// A period during which the OpenSea stream was disconnected, so events may
+/// have been missed.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StreamGap {
+    /// Time the last message was received before the socket died.
+    pub from: SystemTime,
+    /// Time the collector resubscribed.
+    pub to: SystemTime,
+}
+
+/// An item streamed from the socket.
+enum StreamItem {
+    Payload(schema::Payload),
+    Gap(StreamGap),
 }
 
 impl OpenseaOrderCollector {
+    /// Creates a collector listening to listings of every collection on
+    /// mainnet.
     pub fn new(api_key: String) -> Self {
-        Self { api_key }
+        Self {
+            api_key,
+            network: Network::Mainnet,
+            collections: vec![],
+            kinds: HashSet::from([OpenseaEventKind::Listed]),
+            url: None,
+            heartbeat_interval: DEFAULT_HEARTBEAT_INTERVAL,
+            reconnect_delay: DEFAULT_RECONNECT_DELAY,
+        }
+    }
+
+    /// Listens on the given network instead of mainnet.
+    pub fn with_network(mut self, network: Network) -> Self {
+        self.network = network;
+        self
+    }
+
+    /// Only listens to the collections with the given slugs.
+    pub fn with_collections<S: Into<String>>(mut self, slugs: impl IntoIterator<Item = S>) -> Self {
+        self.collections.extend(
+            slugs
+                .into_iter()
+                .map(|slug| Collection::Collection(slug.into())),
+        );
+        self
+    }
+
+    /// Surfaces the given kinds of [OpenseaEvent](OpenseaEvent) instead of
+    /// listings only.
+    pub fn with_event_kinds(mut self, kinds: impl IntoIterator<Item = OpenseaEventKind>) -> Self {
+        self.kinds = kinds.into_iter().collect();
+        self
+    }
+
+    /// Connects to the given websocket endpoint instead of the network's,
+    /// for example a local stand-in.
+    pub fn with_url(mut self, url: impl Into<String>) -> Self {
+        self.url = Some(url.into());
+        self
+    }
+
+    /// Sets the interval between heartbeats, which bounds how long a dead
+    /// socket goes unnoticed.
+    pub fn with_heartbeat_interval(mut self, interval: Duration) -> Self {
+        assert!(!interval.is_zero(), "heartbeat interval must be non-zero");
+        self.heartbeat_interval = interval;
+        self
+    }
+
+    /// Sets the delay before the first reconnection attempt.
+    pub fn with_reconnect_delay(mut self, delay: Duration) -> Self {
+        self.reconnect_delay = delay;
+        self
+    }
+
+    /// Returns the websocket endpoint the collector connects to, carrying
+    /// the API key as its `token` query parameter.
+    pub fn endpoint(&self) -> Result<Url> {
+        let url = match (&self.url, &self.network) {
+            (Some(url), _) => url.as_str(),
+            (None, Network::Mainnet) => MAINNET_STREAM_URL,
+            (None, Network::Testnet) => TESTNET_STREAM_URL,
+        };
+        let mut url = Url::parse(url)?;
+        url.query_pairs_mut().append_pair("token", &self.api_key);
+        Ok(url)
+    }
+
+    /// Returns the channel topics the collector joins.
+    pub fn topics(&self) -> Vec<String> {
+        match self.collections.is_empty() {
+            true => vec![topic(&Collection::All)],
+            false => self.collections.iter().map(topic).collect(),
+        }
+    }
+
+    /// Connects to the stream and joins the channel of every configured
+    /// collection.
+    async fn connect(&self) -> Result<PhoenixSocket> {
+        let endpoint = self.endpoint()?;
+        let topics = self.topics();
+        let join = async {
+            let mut socket = PhoenixSocket::connect(endpoint.as_str()).await?;
+            for topic in &topics {
+                socket.join(topic).await?;
+            }
+            Ok(socket)
+        };
+        timeout(self.heartbeat_interval, join).await?
+    }
+
+    /// Subscribes to the configured collections, streaming the payloads of
+    /// the configured kinds and reporting gaps after reconnecting.
+    async fn payload_stream(&self) -> Result<CollectorStream<'_, StreamItem>> {
+        let mut socket = self.connect().await?;
+
+        let stream = async_stream::stream! {
+            loop {
+                // Listen until the socket dies.
+                let mut heartbeats = interval_at(Instant::now() + self.heartbeat_interval, self.heartbeat_interval);
+                let mut pending_heartbeat: Option<String> = None;
+                let mut last_seen = SystemTime::now();
+                loop {
+                    let message = tokio::select! {
+                        _ = heartbeats.tick() => {
+                            if pending_heartbeat.is_some() {
+                                warn!("opensea stream missed a heartbeat, reconnecting");
+                                break;
+                            }
+                            match socket.heartbeat().await {
+                                Ok(reference) => pending_heartbeat = Some(reference),
+                                Err(e) => {
+                                    warn!("error sending heartbeat to opensea stream: {}", e);
+                                    break;
+                                }
+                            }
+                            continue;
+                        }
+                        message = socket.next_message() => message,
+                    };
+                    let message = match message {
+                        Ok(Some(message)) => message,
+                        Ok(None) => {
+                            warn!("opensea stream closed, reconnecting");
+                            break;
+                        }
+                        Err(e) => {
+                            warn!("error reading opensea stream: {}", e);
+                            break;
+                        }
+                    };
+                    last_seen = SystemTime::now();
+                    match message.event.as_str() {
+                        "phx_reply" => {
+                            if message.reference.is_some() && message.reference == pending_heartbeat {
+                                pending_heartbeat = None;
+                            }
+                        }
+                        "phx_error" | "phx_close" => {
+                            warn!("opensea stream channel {} closed, reconnecting", message.topic);
+                            break;
+                        }
+                        name if !OpenseaEventKind::from_event_name(name).is_some_and(|kind| self.kinds.contains(&kind)) => {
+                            debug!("skipping opensea stream {} event", name);
+                        }
+                        _ => match serde_json::from_value::<schema::StreamEvent>(message.payload) {
+                            Ok(event) => yield StreamItem::Payload(event.payload),
+                            Err(e) => debug!("skipping opensea stream {} event: {}", message.event, e),
+                        },
+                    }
+                }
+
+                // Resubscribe, backing off while the stream is unavailable.
+                let mut delay = self.reconnect_delay;
+                socket = loop {
+                    sleep(delay).await;
+                    match self.connect().await {
+                        Ok(socket) => break socket,
+                        Err(e) => {
+                            warn!("error reconnecting to opensea stream: {}", e);
+                            delay = (delay * 2).min(MAX_RECONNECT_DELAY);
+                        }
+                    }
+                };
+                yield StreamItem::Gap(StreamGap { from: last_seen, to: SystemTime::now() });
+            }
+        };
+
+        Ok(Box::pin(stream))
+    }
+}
+
+/// Returns the channel topic of a collection.
+fn topic(collection: &Collection) -> String {
+    match collection {
+        Collection::All => "collection:*".to_string(),
+        Collection::Collection(slug) => format!("collection:{}", slug),
+    }
+}
+
+impl Default for OpenseaOrderCollector {
+    fn default() -> Self {
+        Self::new(String::default())
     }
 }
 
 /// A new order event, containing the internal order.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct OpenseaOrder {
     pub listing: ItemListedData,
 }
 
-/// Implementation of the [Collector](Collector) trait for the [OpenseaOrderCollector](OpenseaOrderCollector).
-#[async_trait]
-impl Collector<OpenseaOrder> for OpenseaOrderCollector {
-    async fn get_event_stream(&self) -> Result<CollectorStream<OpenseaOrder>> {
-        let mut client = client(Network::Mainnet, &self.api_key).await;
+/// Kinds of [OpenseaEvent](OpenseaEvent) an
+/// [OpenseaOrderCollector](OpenseaOrderCollector) can surface.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum OpenseaEventKind {
+    Listed,
+    Sold,
+    Cancelled,
+    Transferred,
+    ReceivedOffer,
+    CollectionOffer,
+}
 
-        let collection = Collection::All;
+impl OpenseaEventKind {
+    /// Every kind of event.
+    pub const ALL: [Self; 6] = [
+        Self::Listed,
+        Self::Sold,
+        Self::Cancelled,
+        Self::Transferred,
+        Self::ReceivedOffer,
+        Self::CollectionOffer,
+    ];
 
-        let (_, subscription) = subscribe_to(&mut client, collection).await?;
+    /// Returns the name of the stream events of this kind.
+    pub fn event_name(&self) -> &'static str {
+        match self {
+            Self::Listed => "item_listed",
+            Self::Sold => "item_sold",
+            Self::Cancelled => "item_cancelled",
+            Self::Transferred => "item_transferred",
+            Self::ReceivedOffer => "item_received_offer",
+            Self::CollectionOffer => "collection_offer",
+        }
+    }
 
-        let stream = BroadcastStream::new(subscription);
+    /// Returns the kind of the stream events named `name`, or `None` for
+    /// events without a typed [OpenseaEvent](OpenseaEvent).
+    pub fn from_event_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|kind| kind.event_name() == name)
+    }
+}
 
-        let stream = stream.filter_map(|event| {
-            let event = event.ok()?.into_custom_payload()?;
-            if let schema::Payload::ItemListed(listing) = event.payload {
-                Some(OpenseaOrder { listing })
-            } else {
-                None
+/// An event streamed from OpenSea.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum OpenseaEvent {
+    /// An item was listed.
+    Listed(Box<OpenseaOrder>),
+    /// A listed item was sold.
+    Sold(Box<ItemSoldData>),
+    /// A listing was cancelled.
+    Cancelled(Box<ItemCancelledData>),
+    /// An item was transferred.
+    Transferred(Box<ItemTransferredData>),
+    /// An item received an offer.
+    ReceivedOffer(Box<ItemReceivedOfferData>),
+    /// A collection received an offer.
+    CollectionOffer(Box<CollectionOfferData>),
+    /// The stream was disconnected, so events may have been missed.
+    StreamGap(StreamGap),
+}
+
+impl OpenseaEvent {
+    /// Builds the event from a stream payload, returning `None` for payloads
+    /// without a typed event.
+    pub fn from_payload(payload: schema::Payload) -> Option<Self> {
+        match payload {
+            schema::Payload::ItemListed(listing) => {
+                Some(Self::Listed(Box::new(OpenseaOrder { listing })))
+            }
+            schema::Payload::ItemSold(sale) => Some(Self::Sold(Box::new(sale))),
+            schema::Payload::ItemCancelled(cancellation) => {
+                Some(Self::Cancelled(Box::new(cancellation)))
+            }
+            schema::Payload::ItemTransferred(transfer) => {
+                Some(Self::Transferred(Box::new(transfer)))
             }
+            schema::Payload::ItemReceivedOffer(offer) => Some(Self::ReceivedOffer(Box::new(offer))),
+            schema::Payload::CollectionOffer(offer) => Some(Self::CollectionOffer(Box::new(offer))),
+            _ => None,
+        }
+    }
+
+    /// Returns the kind of the event, or `None` for a
+    /// [StreamGap](OpenseaEvent::StreamGap), which is always surfaced.
+    pub fn kind(&self) -> Option<OpenseaEventKind> {
+        match self {
+            Self::Listed(_) => Some(OpenseaEventKind::Listed),
+            Self::Sold(_) => Some(OpenseaEventKind::Sold),
+            Self::Cancelled(_) => Some(OpenseaEventKind::Cancelled),
+            Self::Transferred(_) => Some(OpenseaEventKind::Transferred),
+            Self::ReceivedOffer(_) => Some(OpenseaEventKind::ReceivedOffer),
+            Self::CollectionOffer(_) => Some(OpenseaEventKind::CollectionOffer),
+            Self::StreamGap(_) => None,
+        }
+    }
+}
+
+/// Implementation of the [Collector](Collector) trait for the [OpenseaOrderCollector](OpenseaOrderCollector),
+/// streaming the configured event kinds and gaps.
+#[async_trait]
+impl Collector<OpenseaEvent> for OpenseaOrderCollector {
+    async fn get_event_stream(&self) -> Result<CollectorStream<OpenseaEvent>> {
+        let stream = self.payload_stream().await?;
+
+        let stream = stream.filter_map(|item| match item {
+            StreamItem::Payload(payload) => OpenseaEvent::from_payload(payload),
+            StreamItem::Gap(gap) => Some(OpenseaEvent::StreamGap(gap)),
         });
 
         Ok(Box::pin(stream))
@@ -0,0 +1,312 @@
+use super::block_collector::BlockEvent;
+use crate::types::{Collector, CollectorStream};
+use anyhow::{bail, Result};
+use async_trait::async_trait;
+use ethers::types::{Transaction, H256};
+use futures::stream::{self, StreamExt};
+use std::{
+    collections::{HashMap, VecDeque},
+    hash::Hash,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use tracing::{debug, warn};
+
+/// How long a key is remembered to de-duplicate events.
+const DEFAULT_DEDUP_WINDOW: Duration = Duration::from_secs(60);
+
+/// Largest number of keys remembered to de-duplicate events, however recent.
+const DEFAULT_DEDUP_CAPACITY: usize = 250_000;
+
+/// Delay before first resubscribing to a provider whose stream failed or ended.
+const DEFAULT_RESUBSCRIBE_DELAY: Duration = Duration::from_secs(1);
+
+/// Largest delay between resubscriptions to a provider that keeps failing.
+const DEFAULT_MAX_RESUBSCRIBE_DELAY: Duration = Duration::from_secs(60);
+
+/// A collector that subscribes to the same events from several providers,
+/// such as one [BlockCollector](super::block_collector::BlockCollector) per
+/// RPC endpoint, and forwards each event from whichever provider delivers it
+/// first. Later copies are recognised by their key and dropped, and the delay
+/// of each provider behind the first is recorded in [RaceLatency](RaceLatency).
+///
+/// A provider whose stream fails or ends is resubscribed in the background,
+/// with an exponential backoff, while the other providers keep delivering
+/// events.
+pub struct RacingCollector<E, K> {
+    providers: Vec<(String, Box<dyn Collector<E>>)>,
+    key: Arc<dyn Fn(&E) -> K + Send + Sync>,
+    dedup_window: Duration,
+    dedup_capacity: usize,
+    resubscribe_delay: Duration,
+    max_resubscribe_delay: Duration,
+    latency: RaceLatency,
+}
+
+/// Latency of a provider raced by a [RacingCollector](RacingCollector).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ProviderLatency {
+    /// Number of events the provider delivered, including duplicates.
+    pub delivered: u64,
+    /// Number of events the provider delivered first.
+    pub first: u64,
+    /// Total delay behind the first provider, over the events delivered late.
+    pub total_delay: Duration,
+    /// Largest delay behind the first provider.
+    pub max_delay: Duration,
+}
+
+/// Per-provider latency recorded by a [RacingCollector](RacingCollector),
+/// keyed by provider name. Cheap to clone and can be read while the collector
+/// is running.
+#[derive(Debug, Clone, Default)]
+pub struct RaceLatency {
+    providers: Arc<Mutex<HashMap<String, ProviderLatency>>>,
+}
+
+impl<E, K> RacingCollector<E, K> {
+    /// Creates a collector without providers, de-duplicating events by the
+    /// key returned by `key`.
+    pub fn new(key: impl Fn(&E) -> K + Send + Sync + 'static) -> Self {
+        Self {
+            providers: Vec::new(),
+            key: Arc::new(key),
+            dedup_window: DEFAULT_DEDUP_WINDOW,
+            dedup_capacity: DEFAULT_DEDUP_CAPACITY,
+            resubscribe_delay: DEFAULT_RESUBSCRIBE_DELAY,
+            max_resubscribe_delay: DEFAULT_MAX_RESUBSCRIBE_DELAY,
+            latency: RaceLatency::default(),
+        }
+    }
+
+    /// Adds a provider to the race. `name` identifies it in logs and in
+    /// the recorded latency.
+    pub fn with_provider(
+        mut self,
+        name: impl Into<String>,
+        collector: Box<dyn Collector<E>>,
+    ) -> Self {
+        self.providers.push((name.into(), collector));
+        self
+    }
+
+    /// Sets how long a key is remembered after its first copy. A copy
+    /// arriving after its key was forgotten is forwarded again.
+    pub fn with_dedup_window(mut self, dedup_window: Duration) -> Self {
+        self.dedup_window = dedup_window;
+        self
+    }
+
+    /// Sets how many keys are remembered at most. Once full, the oldest keys
+    /// are forgotten before the end of the window.
+    pub fn with_dedup_capacity(mut self, dedup_capacity: usize) -> Self {
+        self.dedup_capacity = dedup_capacity.max(1);
+        self
+    }
+
+    /// Sets how long to wait before first resubscribing to a failed
+    /// provider. The delay doubles on each consecutive failure.
+    pub fn with_resubscribe_delay(mut self, resubscribe_delay: Duration) -> Self {
+        self.resubscribe_delay = resubscribe_delay;
+        self
+    }
+
+    /// Sets the largest delay between resubscriptions to a failed provider.
+    pub fn with_max_resubscribe_delay(mut self, max_resubscribe_delay: Duration) -> Self {
+        self.max_resubscribe_delay = max_resubscribe_delay;
+        self
+    }
+
+    /// Returns the latency recorded for each provider.
+    pub fn latency(&self) -> RaceLatency {
+        self.latency.clone()
+    }
+}
+
+impl RacingCollector<BlockEvent, (H256, bool)> {
+    /// Creates a collector racing block events, de-duplicated by block hash.
+    /// Reorgs are de-duplicated by the hash of the new chain head.
+    pub fn blocks() -> Self {
+        Self::new(|event: &BlockEvent| match event {
+            BlockEvent::NewBlock(block) => (block.hash, false),
+            BlockEvent::Reorg(reorg) => (
+                reorg
+                    .new_blocks
+                    .last()
+                    .map(|block| block.hash)
+                    .unwrap_or_default(),
+                true,
+            ),
+        })
+    }
+}
+
+impl RacingCollector<Transaction, H256> {
+    /// Creates a collector racing pending transactions, de-duplicated by
+    /// transaction hash.
+    pub fn transactions() -> Self {
+        Self::new(|tx: &Transaction| tx.hash)
+    }
+}
+
+impl<E: Send + 'static, K> RacingCollector<E, K> {
+    /// Streams the events of one provider forever, starting from `initial`
+    /// and resubscribing whenever the stream fails or ends. The delay before
+    /// resubscribing doubles until the provider delivers an event again.
+    fn provider_stream<'a>(
+        &'a self,
+        index: usize,
+        initial: Option<CollectorStream<'a, E>>,
+    ) -> CollectorStream<'a, (usize, E)> {
+        let (name, collector) = &self.providers[index];
+        let stream = async_stream::stream! {
+            let mut current = initial;
+            let mut delay = self.resubscribe_delay;
+            loop {
+                let mut events = match current.take() {
+                    Some(events) => events,
+                    None => match collector.get_event_stream().await {
+                        Ok(events) => events,
+                        Err(e) => {
+                            warn!("racing provider {} failed to subscribe, retrying in {:?}: {}", name, delay, e);
+                            tokio::time::sleep(delay).await;
+                            delay = self.next_resubscribe_delay(delay);
+                            continue;
+                        }
+                    },
+                };
+                while let Some(event) = events.next().await {
+                    delay = self.resubscribe_delay;
+                    yield (index, event);
+                }
+                warn!("racing provider {} stream ended, resubscribing in {:?}", name, delay);
+                tokio::time::sleep(delay).await;
+                delay = self.next_resubscribe_delay(delay);
+            }
+        };
+        Box::pin(stream)
+    }
+
+    /// Returns the delay before the next resubscription, doubling `delay`
+    /// up to the largest resubscribe delay.
+    fn next_resubscribe_delay(&self, delay: Duration) -> Duration {
+        delay
+            .saturating_mul(2)
+            .min(self.max_resubscribe_delay)
+            .max(self.resubscribe_delay)
+    }
+}
+
+/// Implementation of the [Collector](Collector) trait for the [RacingCollector](RacingCollector).
+/// Fails only if no provider can be subscribed to.
+#[async_trait]
+impl<E, K> Collector<E> for RacingCollector<E, K>
+where
+    E: Send + 'static,
+    K: Clone + Hash + Eq + Send + 'static,
+{
+    async fn get_event_stream(&self) -> Result<CollectorStream<E>> {
+        let subscriptions = futures::future::join_all(
+            self.providers
+                .iter()
+                .map(|(_, collector)| collector.get_event_stream()),
+        )
+        .await;
+        let mut streams = Vec::with_capacity(subscriptions.len());
+        let mut subscribed = 0;
+        for (index, subscription) in subscriptions.into_iter().enumerate() {
+            let initial = match subscription {
+                Ok(events) => {
+                    subscribed += 1;
+                    Some(events)
+                }
+                Err(e) => {
+                    warn!(
+                        "racing provider {} failed to subscribe: {}",
+                        self.providers[index].0, e
+                    );
+                    None
+                }
+            };
+            streams.push(self.provider_stream(index, initial));
+        }
+        if subscribed == 0 {
+            bail!("no racing provider could be subscribed to");
+        }
+
+        let mut events = stream::select_all(streams);
+        let stream = async_stream::stream! {
+            let mut seen: HashMap<K, Instant> = HashMap::new();
+            let mut order: VecDeque<K> = VecDeque::new();
+            while let Some((index, event)) = events.next().await {
+                let name = &self.providers[index].0;
+                let key = (self.key)(&event);
+                let now = Instant::now();
+                // Forget keys first seen before the window, and the oldest
+                // keys beyond the capacity.
+                while let Some(oldest) = order.front() {
+                    if now.duration_since(seen[oldest]) < self.dedup_window && order.len() < self.dedup_capacity {
+                        break;
+                    }
+                    seen.remove(oldest);
+                    order.pop_front();
+                }
+                if let Some(first_seen) = seen.get(&key) {
+                    let delay = now.duration_since(*first_seen);
+                    debug!("racing provider {} delivered an event {:?} late", name, delay);
+                    self.latency.record(name, Some(delay));
+                    continue;
+                }
+                self.latency.record(name, None);
+                order.push_back(key.clone());
+                seen.insert(key, now);
+                yield event;
+            }
+        };
+        Ok(Box::pin(stream))
+    }
+}
+
+impl ProviderLatency {
+    /// Returns the average delay behind the first provider, over the events
+    /// the provider delivered late.
+    pub fn mean_delay(&self) -> Duration {
+        let late = self.delivered - self.first;
+        if late == 0 {
+            return Duration::ZERO;
+        }
+        self.total_delay / u32::try_from(late).unwrap_or(u32::MAX)
+    }
+}
+
+impl RaceLatency {
+    /// Returns the latency recorded for the given provider.
+    pub fn get(&self, name: &str) -> ProviderLatency {
+        self.providers
+            .lock()
+            .unwrap()
+            .get(name)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Returns the latency recorded for every provider that delivered an event.
+    pub fn snapshot(&self) -> HashMap<String, ProviderLatency> {
+        self.providers.lock().unwrap().clone()
+    }
+
+    /// Records an event delivered by `name`, either first or `delay` behind
+    /// the first provider.
+    fn record(&self, name: &str, delay: Option<Duration>) {
+        let mut providers = self.providers.lock().unwrap();
+        let latency = providers.entry(name.to_string()).or_default();
+        latency.delivered += 1;
+        match delay {
+            Some(delay) => {
+                latency.total_delay += delay;
+                latency.max_delay = latency.max_delay.max(delay);
+            }
+            None => latency.first += 1,
+        }
+    }
+}
@@ -0,0 +1,113 @@
+use std::{marker::PhantomData, path::PathBuf, time::Duration};
+
+use crate::types::{Collector, CollectorStream, EventEnvelope};
+use anyhow::Result;
+use async_trait::async_trait;
+use serde::de::DeserializeOwned;
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tracing::error;
+
+/// A collector that replays events recorded by an
+/// [EventRecorder](crate::utilities::event_recorder::EventRecorder), in the
+/// order they were recorded. Execution reports are skipped, since the engine
+/// publishes them again as the replayed actions execute. The stream ends once
+/// the recording is exhausted, so the collector is usually added with
+/// [RestartPolicy::never](crate::engine::RestartPolicy::never).
+pub struct ReplayCollector<E> {
+    path: PathBuf,
+    speed: ReplaySpeed,
+    _event: PhantomData<fn() -> E>,
+}
+
+/// Pace at which recorded events are replayed.
+#[derive(Debug, Clone, Copy)]
+pub enum ReplaySpeed {
+    /// Replay events with the same delays between them as when recorded.
+    Recorded,
+    /// Replay events with the recorded delays divided by the given factor,
+    /// which must be finite and positive.
+    Accelerated(f64),
+    /// Replay events as fast as they can be processed.
+    Unthrottled,
+}
+
+impl<E> ReplayCollector<E> {
+    pub fn new(path: impl Into<PathBuf>, speed: ReplaySpeed) -> Self {
+        if let ReplaySpeed::Accelerated(factor) = speed {
+            assert!(
+                factor.is_finite() && factor > 0.0,
+                "replay acceleration must be finite and positive, got {}",
+                factor
+            );
+        }
+        Self {
+            path: path.into(),
+            speed,
+            _event: PhantomData,
+        }
+    }
+}
+
+impl ReplaySpeed {
+    /// Returns how long to wait before replaying an event, given the recorded
+    /// delay since the previous one.
+    fn delay(&self, recorded: Duration) -> Option<Duration> {
+        match self {
+            ReplaySpeed::Recorded => Some(recorded),
+            ReplaySpeed::Accelerated(factor) => Some(recorded.div_f64(*factor)),
+            ReplaySpeed::Unthrottled => None,
+        }
+    }
+}
+
+/// Implementation of the [Collector](Collector) trait for the [ReplayCollector](ReplayCollector).
+#[async_trait]
+impl<E> Collector<E> for ReplayCollector<E>
+where
+    E: DeserializeOwned + Send + 'static,
+{
+    async fn get_event_stream(&self) -> Result<CollectorStream<E>> {
+        let recording = tokio::fs::File::open(&self.path).await?;
+        let speed = self.speed;
+
+        let stream = async_stream::stream! {
+            let mut lines = BufReader::new(recording).lines();
+            let mut previous = None;
+            loop {
+                let line = match lines.next_line().await {
+                    Ok(Some(line)) => line,
+                    Ok(None) => break,
+                    Err(e) => {
+                        error!("error reading recorded events: {}", e);
+                        break;
+                    }
+                };
+                if line.trim().is_empty() {
+                    continue;
+                }
+                let envelope: EventEnvelope<E> = match serde_json::from_str(&line) {
+                    Ok(envelope) => envelope,
+                    Err(e) => {
+                        error!("skipping malformed recorded event: {}", e);
+                        continue;
+                    }
+                };
+                if envelope.execution_report {
+                    continue;
+                }
+
+                let recorded = previous
+                    .and_then(|previous| envelope.received_at.duration_since(previous).ok())
+                    .unwrap_or_default();
+                if let Some(delay) = speed.delay(recorded) {
+                    tokio::time::sleep(delay).await;
+                }
+                previous = Some(envelope.received_at);
+
+                yield envelope.event;
+            }
+        };
+
+        Ok(Box::pin(stream))
+    }
+}
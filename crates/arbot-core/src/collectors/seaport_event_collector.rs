@@ -0,0 +1,93 @@
+use std::sync::Arc;
+
+use super::log_collector::{DecodedLog, DecodedLogCollector, LogCollector};
+use crate::types::{Collector, CollectorStream};
+use anyhow::Result;
+use async_trait::async_trait;
+use ethers::{
+    contract::{abigen, EthEvent},
+    prelude::Middleware,
+    providers::PubsubClient,
+    types::{Address, Filter, ValueOrArray, U64},
+};
+use opensea_v2::constants::{SEAPORT_V1, SEAPORT_V4};
+
+// Seaport 1.4 emits the parameters of validated orders, where 1.1 emitted
+// their offerer and zone. The overloaded event is generated as
+// `OrderValidated1Filter` for 1.1 and `OrderValidated2Filter` for 1.4.
+abigen!(
+    Seaport,
+    r#"[
+        struct SpentItem { uint8 itemType; address token; uint256 identifier; uint256 amount; }
+        struct ReceivedItem { uint8 itemType; address token; uint256 identifier; uint256 amount; address recipient; }
+        struct OfferItem { uint8 itemType; address token; uint256 identifierOrCriteria; uint256 startAmount; uint256 endAmount; }
+        struct ConsiderationItem { uint8 itemType; address token; uint256 identifierOrCriteria; uint256 startAmount; uint256 endAmount; address recipient; }
+        struct OrderParameters { address offerer; address zone; OfferItem[] offer; ConsiderationItem[] consideration; uint8 orderType; uint256 startTime; uint256 endTime; bytes32 zoneHash; uint256 salt; bytes32 conduitKey; uint256 totalOriginalConsiderationItems; }
+        event OrderFulfilled(bytes32 orderHash, address indexed offerer, address indexed zone, address recipient, SpentItem[] offer, ReceivedItem[] consideration)
+        event OrderCancelled(bytes32 orderHash, address indexed offerer, address indexed zone)
+        event OrderValidated(bytes32 orderHash, address indexed offerer, address indexed zone)
+        event OrderValidated(bytes32 orderHash, OrderParameters orderParameters)
+        event CounterIncremented(uint256 newCounter, address indexed offerer)
+    ]"#,
+);
+
+/// A collector that listens for order fills, cancellations, validations and
+/// counter increments on Seaport, and generates a stream of
+/// [events](SeaportEvents) decoded with the Seaport event ABI. Validations are
+/// decoded in both the Seaport 1.1 and 1.4 formats. By default it listens to
+/// the Seaport V1 and V4 deployments.
+pub struct SeaportEventCollector<M> {
+    inner: DecodedLogCollector<M, SeaportEvents>,
+}
+
+impl<M> SeaportEventCollector<M> {
+    /// Creates a collector listening to the Seaport V1 and V4 deployments.
+    pub fn new(provider: Arc<M>) -> Self {
+        let deployments =
+            [SEAPORT_V1, SEAPORT_V4].map(|address| address.parse().expect("valid seaport address"));
+        Self::with_deployments(provider, deployments)
+    }
+
+    /// Creates a collector listening to the given Seaport deployments.
+    pub fn with_deployments(
+        provider: Arc<M>,
+        deployments: impl IntoIterator<Item = Address>,
+    ) -> Self {
+        let signatures = vec![
+            Some(OrderFulfilledFilter::signature()),
+            Some(OrderCancelledFilter::signature()),
+            Some(OrderValidated1Filter::signature()),
+            Some(OrderValidated2Filter::signature()),
+            Some(CounterIncrementedFilter::signature()),
+        ];
+        let filter = Filter::new()
+            .address(deployments.into_iter().collect::<Vec<_>>())
+            .topic0(ValueOrArray::Array(signatures));
+        Self::from_log_collector(LogCollector::new(provider, filter))
+    }
+
+    /// Backfills events from `block` up to the chain head before streaming
+    /// live events.
+    pub fn with_backfill_from(self, block: impl Into<U64>) -> Self {
+        Self::from_log_collector(self.inner.into_inner().with_backfill_from(block))
+    }
+
+    fn from_log_collector(collector: LogCollector<M>) -> Self {
+        Self {
+            inner: collector.decoded_events(),
+        }
+    }
+}
+
+/// Implementation of the [Collector](Collector) trait for the [SeaportEventCollector](SeaportEventCollector).
+#[async_trait]
+impl<M> Collector<DecodedLog<SeaportEvents>> for SeaportEventCollector<M>
+where
+    M: Middleware,
+    M::Provider: PubsubClient,
+    M::Error: 'static,
+{
+    async fn get_event_stream(&self) -> Result<CollectorStream<DecodedLog<SeaportEvents>>> {
+        self.inner.get_event_stream().await
+    }
+}
@@ -0,0 +1,146 @@
+use std::time::Duration;
+
+use ethers::{
+    prelude::Middleware,
+    providers::{MiddlewareError, PubsubClient},
+    types::{Block, TxHash, H256},
+};
+use futures::{future::BoxFuture, StreamExt};
+use tracing::{error, warn};
+
+use crate::types::CollectorStream;
+use anyhow::Result;
+
+/// Number of times a block announced by a block filter is fetched again
+/// before the stream ends.
+const BLOCK_FETCH_RETRIES: usize = 3;
+
+/// Opens the stream of new blocks a [BlockCollector](super::block_collector::BlockCollector)
+/// follows, either by subscribing or by polling.
+pub(crate) type BlockSource<M> =
+    for<'a> fn(&'a M) -> BoxFuture<'a, Result<CollectorStream<'a, Block<TxHash>>>>;
+
+/// Opens the stream of pending transaction hashes a
+/// [MempoolCollector](super::mempool_collector::MempoolCollector) follows,
+/// either by subscribing or by polling.
+pub(crate) type PendingTxSource<M> =
+    for<'a> fn(&'a M) -> BoxFuture<'a, Result<CollectorStream<'a, TxHash>>>;
+
+/// Subscribes to new blocks, polling for them instead if the node rejects
+/// the subscription.
+pub(crate) fn subscribe_blocks<M>(
+    provider: &M,
+) -> BoxFuture<'_, Result<CollectorStream<'_, Block<TxHash>>>>
+where
+    M: Middleware,
+    M::Provider: PubsubClient,
+    M::Error: 'static,
+{
+    Box::pin(async move {
+        match provider.subscribe_blocks().await {
+            Ok(blocks) => Ok(Box::pin(blocks) as CollectorStream<_>),
+            Err(e) if e.is_error_response() => {
+                warn!(
+                    "node does not support block subscriptions, polling instead: {}",
+                    e
+                );
+                poll_blocks(provider).await
+            }
+            Err(e) => Err(e.into()),
+        }
+    })
+}
+
+/// Subscribes to new pending transactions, polling for them instead if the
+/// node rejects the subscription.
+pub(crate) fn subscribe_pending_txs<M>(
+    provider: &M,
+) -> BoxFuture<'_, Result<CollectorStream<'_, TxHash>>>
+where
+    M: Middleware,
+    M::Provider: PubsubClient,
+    M::Error: 'static,
+{
+    Box::pin(async move {
+        match provider.subscribe_pending_txs().await {
+            Ok(hashes) => Ok(Box::pin(hashes) as CollectorStream<_>),
+            Err(e) if e.is_error_response() => {
+                warn!(
+                    "node does not support pending transaction subscriptions, polling instead: {}",
+                    e
+                );
+                poll_pending_txs(provider).await
+            }
+            Err(e) => Err(e.into()),
+        }
+    })
+}
+
+/// Polls a block filter at the provider's interval, fetching each new block.
+/// A block that fails to be fetched is retried, and the stream ends if it
+/// keeps failing, so that the engine restarts the collector. A block that is
+/// no longer found was reorged out and is skipped; the block collector fills
+/// gaps from parent hashes.
+pub(crate) fn poll_blocks<M>(
+    provider: &M,
+) -> BoxFuture<'_, Result<CollectorStream<'_, Block<TxHash>>>>
+where
+    M: Middleware,
+    M::Error: 'static,
+{
+    Box::pin(async move {
+        let mut hashes = provider.watch_blocks().await?;
+        let retry_delay = provider.provider().get_interval();
+        let stream = async_stream::stream! {
+            while let Some(hash) = hashes.next().await {
+                match fetch_block(provider, hash, retry_delay).await {
+                    Ok(Some(block)) => yield block,
+                    Ok(None) => warn!(?hash, "polled block no longer found, skipping it"),
+                    Err(e) => {
+                        error!(?hash, "error fetching polled block: {}", e);
+                        return;
+                    }
+                }
+            }
+        };
+        Ok(Box::pin(stream) as CollectorStream<_>)
+    })
+}
+
+/// Polls a pending transaction filter at the provider's interval.
+pub(crate) fn poll_pending_txs<M>(
+    provider: &M,
+) -> BoxFuture<'_, Result<CollectorStream<'_, TxHash>>>
+where
+    M: Middleware,
+    M::Error: 'static,
+{
+    Box::pin(async move {
+        Ok(Box::pin(provider.watch_pending_transactions().await?) as CollectorStream<_>)
+    })
+}
+
+/// Fetches a block by hash, retrying failed requests after `retry_delay`.
+async fn fetch_block<M>(
+    provider: &M,
+    hash: H256,
+    retry_delay: Duration,
+) -> Result<Option<Block<TxHash>>, M::Error>
+where
+    M: Middleware,
+{
+    let mut attempt = 0;
+    loop {
+        match provider.get_block(hash).await {
+            Err(e) if attempt < BLOCK_FETCH_RETRIES => {
+                attempt += 1;
+                warn!(
+                    ?hash,
+                    attempt, "error fetching polled block, retrying: {}", e
+                );
+                tokio::time::sleep(retry_delay).await;
+            }
+            result => return result,
+        }
+    }
+}
@@ -0,0 +1,274 @@
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+
+use anyhow::{bail, Result};
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{header, Body, Method, Request, Response, Server, StatusCode};
+use serde::Serialize;
+use serde_json::{json, Value};
+use thiserror::Error;
+use tokio::sync::{mpsc, oneshot, watch};
+use tracing::info;
+
+use crate::engine::{Health, HealthMonitor};
+
+/// Kind of a component managed by the engine.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ComponentKind {
+    Collector,
+    Strategy,
+    Executor,
+}
+
+/// Status of a component, as listed by [components](ControlHandle::components).
+#[derive(Debug, Clone, Serialize)]
+pub struct ComponentStatus {
+    pub name: String,
+    pub kind: ComponentKind,
+    /// Health of the component, if it has started.
+    pub health: Option<Health>,
+    /// Whether the component is paused.
+    pub paused: bool,
+}
+
+/// Error returned when a control command cannot be carried out.
+#[derive(Error, Debug)]
+pub enum ControlError {
+    #[error("no component named {0}")]
+    UnknownComponent(String),
+    #[error("{0} does not support {1}")]
+    Unsupported(String, &'static str),
+    #[error("{0} has stopped")]
+    Stopped(String),
+}
+
+impl ControlError {
+    fn status(&self) -> StatusCode {
+        match self {
+            ControlError::UnknownComponent(_) => StatusCode::NOT_FOUND,
+            ControlError::Unsupported(..) => StatusCode::BAD_REQUEST,
+            ControlError::Stopped(_) => StatusCode::CONFLICT,
+        }
+    }
+}
+
+/// Commands carried out by a strategy's task.
+pub(crate) enum StrategyCommand {
+    Resync,
+    Summary(oneshot::Sender<Value>),
+}
+
+/// Control channels handed to a running strategy.
+pub(crate) struct StrategyControl {
+    pub(crate) paused: watch::Receiver<bool>,
+    pub(crate) commands: mpsc::Receiver<StrategyCommand>,
+}
+
+/// Control channels of a registered component.
+struct ComponentControl {
+    name: String,
+    kind: ComponentKind,
+    paused: Option<watch::Sender<bool>>,
+    commands: Option<mpsc::Sender<StrategyCommand>>,
+}
+
+/// A handle used to inspect and control the components of a running
+/// [Engine](crate::engine::Engine): pause and resume strategies and executors,
+/// resync strategies and dump their state. Handles are cheap to clone, and
+/// can be served over HTTP with [serve](ControlHandle::serve).
+#[derive(Clone)]
+pub struct ControlHandle {
+    components: Arc<Mutex<Vec<ComponentControl>>>,
+    health: HealthMonitor,
+}
+
+impl ControlHandle {
+    pub(crate) fn new(health: HealthMonitor) -> Self {
+        Self {
+            components: Arc::new(Mutex::new(vec![])),
+            health,
+        }
+    }
+
+    pub(crate) fn register_collector(&self, name: &str) {
+        self.register(ComponentControl {
+            name: name.to_string(),
+            kind: ComponentKind::Collector,
+            paused: None,
+            commands: None,
+        });
+    }
+
+    pub(crate) fn register_strategy(&self, name: &str) -> StrategyControl {
+        let (paused_sender, paused) = watch::channel(false);
+        let (commands_sender, commands) = mpsc::channel(16);
+        self.register(ComponentControl {
+            name: name.to_string(),
+            kind: ComponentKind::Strategy,
+            paused: Some(paused_sender),
+            commands: Some(commands_sender),
+        });
+        StrategyControl { paused, commands }
+    }
+
+    pub(crate) fn register_executor(&self, name: &str) -> watch::Receiver<bool> {
+        let (paused_sender, paused) = watch::channel(false);
+        self.register(ComponentControl {
+            name: name.to_string(),
+            kind: ComponentKind::Executor,
+            paused: Some(paused_sender),
+            commands: None,
+        });
+        paused
+    }
+
+    fn register(&self, control: ComponentControl) {
+        self.components.lock().unwrap().push(control);
+    }
+
+    /// Lists every component of the running engine.
+    pub fn components(&self) -> Vec<ComponentStatus> {
+        self.components
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|control| ComponentStatus {
+                name: control.name.clone(),
+                kind: control.kind,
+                health: self.health.get(&control.name),
+                paused: control
+                    .paused
+                    .as_ref()
+                    .is_some_and(|paused| *paused.borrow()),
+            })
+            .collect()
+    }
+
+    /// Pauses a strategy or executor. A paused component stops consuming its
+    /// channel, so it may lag behind once resumed.
+    pub fn pause(&self, name: &str) -> Result<(), ControlError> {
+        self.set_paused(name, true)
+    }
+
+    /// Resumes a paused strategy or executor.
+    pub fn resume(&self, name: &str) -> Result<(), ControlError> {
+        self.set_paused(name, false)
+    }
+
+    fn set_paused(&self, name: &str, paused: bool) -> Result<(), ControlError> {
+        let components = self.components.lock().unwrap();
+        let control = find(&components, name)?;
+        match &control.paused {
+            Some(sender) => {
+                sender.send_replace(paused);
+                Ok(())
+            }
+            None => Err(ControlError::Unsupported(name.to_string(), "pausing")),
+        }
+    }
+
+    /// Makes a strategy call [sync_state](crate::types::Strategy::sync_state)
+    /// again before processing further events.
+    pub async fn resync(&self, name: &str) -> Result<(), ControlError> {
+        let commands = self.commands(name, "resyncing")?;
+        commands
+            .send(StrategyCommand::Resync)
+            .await
+            .map_err(|_| ControlError::Stopped(name.to_string()))
+    }
+
+    /// Returns a strategy's [state_summary](crate::types::Strategy::state_summary).
+    pub async fn state_summary(&self, name: &str) -> Result<Value, ControlError> {
+        let commands = self.commands(name, "state summaries")?;
+        let (reply, summary) = oneshot::channel();
+        commands
+            .send(StrategyCommand::Summary(reply))
+            .await
+            .map_err(|_| ControlError::Stopped(name.to_string()))?;
+        summary
+            .await
+            .map_err(|_| ControlError::Stopped(name.to_string()))
+    }
+
+    fn commands(
+        &self,
+        name: &str,
+        command: &'static str,
+    ) -> Result<mpsc::Sender<StrategyCommand>, ControlError> {
+        let components = self.components.lock().unwrap();
+        let control = find(&components, name)?;
+        control
+            .commands
+            .clone()
+            .ok_or_else(|| ControlError::Unsupported(name.to_string(), command))
+    }
+
+    /// Serves the control plane over HTTP at the given loopback address until
+    /// the server fails. The control plane is unauthenticated, so it refuses
+    /// to listen on other addresses.
+    ///
+    /// * `GET /components` lists every component with its health.
+    /// * `POST /components/{name}/pause` and `/resume` pause and resume a
+    ///   strategy or executor.
+    /// * `POST /components/{name}/resync` resyncs a strategy.
+    /// * `GET /components/{name}/state` returns a strategy's state summary.
+    pub async fn serve(self, addr: SocketAddr) -> Result<()> {
+        if !addr.ip().is_loopback() {
+            bail!(
+                "control plane must listen on a loopback address, not {}",
+                addr
+            );
+        }
+        let make_service = make_service_fn(move |_| {
+            let control = self.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |request| {
+                    let control = control.clone();
+                    async move { Ok::<_, Infallible>(control.respond(request).await) }
+                }))
+            }
+        });
+        let server = Server::try_bind(&addr)?.serve(make_service);
+        info!("serving control plane on http://{}", server.local_addr());
+        server.await?;
+        Ok(())
+    }
+
+    async fn respond(&self, request: Request<Body>) -> Response<Body> {
+        let segments: Vec<&str> = request.uri().path().trim_matches('/').split('/').collect();
+        let result = match (request.method(), segments.as_slice()) {
+            (&Method::GET, ["components"]) => Ok(json!(self.components())),
+            (&Method::POST, ["components", name, "pause"]) => self.pause(name).map(|_| json!({})),
+            (&Method::POST, ["components", name, "resume"]) => self.resume(name).map(|_| json!({})),
+            (&Method::POST, ["components", name, "resync"]) => {
+                self.resync(name).await.map(|_| json!({}))
+            }
+            (&Method::GET, ["components", name, "state"]) => self.state_summary(name).await,
+            _ => return json_response(StatusCode::NOT_FOUND, json!({ "error": "not found" })),
+        };
+        match result {
+            Ok(body) => json_response(StatusCode::OK, body),
+            Err(e) => json_response(e.status(), json!({ "error": e.to_string() })),
+        }
+    }
+}
+
+fn find<'a>(
+    components: &'a [ComponentControl],
+    name: &str,
+) -> Result<&'a ComponentControl, ControlError> {
+    components
+        .iter()
+        .find(|control| control.name == name)
+        .ok_or_else(|| ControlError::UnknownComponent(name.to_string()))
+}
+
+fn json_response(status: StatusCode, body: Value) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(body.to_string()))
+        .unwrap()
+}
@@ -1,31 +1,188 @@
-use tokio::sync::broadcast::{self, Sender};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant, SystemTime};
+
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use serde::Serialize;
+
+use tokio::sync::broadcast::{self, error::RecvError, Receiver, Sender};
+use tokio::sync::oneshot::{self, error::TryRecvError};
+use tokio::sync::{mpsc, watch, Semaphore};
 use tokio::task::JoinSet;
 use tokio_stream::StreamExt;
-use tracing::{error, info};
+use tracing::{debug, error, info, info_span, warn, Instrument};
+
+use crate::control::{ControlHandle, StrategyCommand, StrategyControl};
+use crate::metrics::{EngineMetrics, ExecutionOutcome};
+use crate::types::{
+    hold_ordering, ActionEnvelope, ActionId, ActionSink, Collector, EventEnvelope, EventFilter,
+    EventId, Execution, ExecutionReport, Executor, LagPolicy, SharedSender, Strategy,
+};
+use crate::utilities::event_recorder::EventRecorder;
+use crate::utilities::snapshot_store::SnapshotStore;
+
+/// Default capacity of the event and action channels.
+const DEFAULT_CHANNEL_CAPACITY: usize = 512;
+
+/// Default amount of time executors get to finish in-flight actions once
+/// shutdown has been triggered.
+const DEFAULT_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(30);
 
-use crate::types::{Collector, Executor, Strategy};
+/// Name under which the event router reports its health and lag.
+const ROUTER_NAME: &str = "EventRouter";
 
 /// The main engine of Artemis. This struct is responsible for orchestrating the
 /// data flow between collectors, strategies, and executors.
 pub struct Engine<E, A> {
-    /// The set of collectors that the engine will use to collect events.   // 收集器 用于收集事件
-    collectors: Vec<Box<dyn Collector<E>>>,
+    /// The set of collectors that the engine will use to collect events,   // 收集器 用于收集事件
+    /// along with the policy used to restart them.
+    collectors: Vec<(Box<dyn Collector<E>>, RestartPolicy)>,
 
     /// The set of strategies that the engine will use to process events.   // 策略 用于处理事件
     strategies: Vec<Box<dyn Strategy<E, A>>>,
 
-    /// The set of executors that the engine will use to execute actions.   // 执行器 用于执行动作
-    executors: Vec<Box<dyn Executor<A>>>,
+    /// The set of executors that the engine will use to execute actions,  // 执行器 用于执行动作
+    /// along with the number of actions each may execute concurrently.
+    executors: Vec<(Box<dyn Executor<A>>, usize)>,
+
+    /// Handle used to stop the engine once it is running.                  // 用于停止引擎
+    shutdown: ShutdownHandle,
+
+    /// How long executors may keep working on in-flight actions after shutdown.
+    shutdown_timeout: Duration,
+
+    /// Capacity of the channel carrying events from collectors to strategies.
+    event_channel_capacity: usize,
+
+    /// Capacity of the channel carrying actions from strategies to executors.
+    action_channel_capacity: usize,
+
+    /// Number of messages missed by each strategy and executor.
+    lag_counters: LagCounters,
+
+    /// Health of each running component.
+    health: HealthMonitor,
+
+    /// Prometheus metrics recorded for each component.
+    metrics: EngineMetrics,
+
+    /// Handle used to pause, resume and inspect components once running.
+    control: ControlHandle,
+
+    /// Policy used to retry resyncing a strategy after it failed.
+    resync_policy: RestartPolicy,
+
+    /// Store used to persist strategy snapshots, if snapshots are enabled.
+    snapshots: Option<SnapshotStore>,
+
+    /// Task recording every event, if recording is enabled.
+    recorder: Option<RecorderTask<E>>,
+
+    /// Maps execution reports to events, if reporting is enabled.
+    reporter: Option<ReportFn<E, A>>,
 }
 
+/// Maps an [ExecutionReport](ExecutionReport) to an event, if strategies should see it.
+type ReportFn<E, A> = Arc<dyn Fn(ExecutionReport<A>) -> Option<E> + Send + Sync>;
+
+/// Task which records the events received on the event channel.
+type RecorderTask<E> = Box<dyn FnOnce(Receiver<EventEnvelope<E>>) -> BoxFuture<'static, ()> + Send>;
+
 impl<E, A> Engine<E, A> {
     pub fn new() -> Self {
+        let health = HealthMonitor::default();
         Self {
             collectors: vec![],
             strategies: vec![],
             executors: vec![],
+            shutdown: ShutdownHandle::new(),
+            shutdown_timeout: DEFAULT_SHUTDOWN_TIMEOUT,
+            event_channel_capacity: DEFAULT_CHANNEL_CAPACITY,
+            action_channel_capacity: DEFAULT_CHANNEL_CAPACITY,
+            lag_counters: LagCounters::default(),
+            control: ControlHandle::new(health.clone()),
+            health,
+            metrics: EngineMetrics::default(),
+            resync_policy: RestartPolicy::default(),
+            snapshots: None,
+            recorder: None,
+            reporter: None,
         }
     }
+
+    /// Sets the policy used to retry [sync_state](Strategy::sync_state) when
+    /// a strategy failed to process an event.
+    pub fn with_resync_policy(mut self, resync_policy: RestartPolicy) -> Self {
+        self.resync_policy = resync_policy;
+        self
+    }
+
+    /// Restores each strategy from its latest snapshot in `store` before
+    /// syncing it, and snapshots it periodically and once it stops. See
+    /// [snapshot](Strategy::snapshot).
+    pub fn with_snapshots(mut self, store: SnapshotStore) -> Self {
+        self.snapshots = Some(store);
+        self
+    }
+
+    /// Returns a handle reporting the health of each component while the
+    /// engine is running.
+    pub fn health(&self) -> HealthMonitor {
+        self.health.clone()
+    }
+
+    /// Records metrics into the given [EngineMetrics](EngineMetrics), for
+    /// example to share a registry with custom metrics.
+    pub fn with_metrics(mut self, metrics: EngineMetrics) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    /// Returns the Prometheus metrics recorded for each component, which can
+    /// be served with [serve](EngineMetrics::serve).
+    pub fn metrics(&self) -> EngineMetrics {
+        self.metrics.clone()
+    }
+
+    /// Returns a handle used to list, pause, resume and inspect components
+    /// while the engine is running, which can be served with
+    /// [serve](ControlHandle::serve).
+    pub fn control(&self) -> ControlHandle {
+        self.control.clone()
+    }
+
+    /// Sets the capacity of the event channel. A strategy that falls more than
+    /// `capacity` events behind misses the oldest ones.
+    pub fn with_event_channel_capacity(mut self, capacity: usize) -> Self {
+        self.event_channel_capacity = capacity;
+        self
+    }
+
+    /// Sets the capacity of the action channel. An executor that falls more
+    /// than `capacity` actions behind misses the oldest ones.
+    pub fn with_action_channel_capacity(mut self, capacity: usize) -> Self {
+        self.action_channel_capacity = capacity;
+        self
+    }
+
+    /// Returns the counters tracking how many messages each strategy and
+    /// executor missed because it fell behind its channel.
+    pub fn lag_counters(&self) -> LagCounters {
+        self.lag_counters.clone()
+    }
+
+    /// Sets how long executors may keep working on in-flight actions once
+    /// shutdown has been triggered.
+    pub fn with_shutdown_timeout(mut self, shutdown_timeout: Duration) -> Self {
+        self.shutdown_timeout = shutdown_timeout;
+        self
+    }
+
+    /// Returns a handle that can be used to shut down the engine.
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        self.shutdown.clone()
+    }
 }
 
 impl<E, A> Default for Engine<E, A> {
@@ -34,91 +191,1112 @@ impl<E, A> Default for Engine<E, A> {
     }
 }
 
-impl<E, A> Engine<E, A> 
-    where
+impl<E, A> Engine<E, A>
+where
     E: Send + Clone + 'static + std::fmt::Debug,
     A: Send + Clone + 'static + std::fmt::Debug,
 {
     /// Adds a collector to be used by the engine.  // 添加收集器 用于引擎
-    pub fn add_collector(&mut self, collector: Box<dyn Collector<E>>) {
-        self.collectors.push(collector);
+    /// The collector is restarted with the default [RestartPolicy](RestartPolicy).
+    pub fn add_collector(&mut self, collector: Box<dyn Collector<E>>) -> anyhow::Result<()> {
+        self.add_collector_with_policy(collector, RestartPolicy::default())
+    }
+
+    /// Adds a collector which is restarted according to the given policy
+    /// whenever its event stream fails or ends.
+    pub fn add_collector_with_policy(
+        &mut self,
+        collector: Box<dyn Collector<E>>,
+        policy: RestartPolicy,
+    ) -> anyhow::Result<()> {
+        self.check_unique_name(collector.name())?;
+        self.collectors.push((collector, policy));
+        Ok(())
     }
 
     /// Adds a strategy to be used by the engine.   // 添加策略 用于引擎
-    pub fn add_strategy(&mut self, strategy: Box<dyn Strategy<E, A>>) {
+    pub fn add_strategy(&mut self, strategy: Box<dyn Strategy<E, A>>) -> anyhow::Result<()> {
+        self.check_unique_name(strategy.name())?;
         self.strategies.push(strategy);
+        Ok(())
     }
 
     /// Adds an executor to be used by the engine.  // 添加执行器 用于引擎
-    pub fn add_executor(&mut self, executor: Box<dyn Executor<A>>) {
-        self.executors.push(executor);
+    /// The executor handles one action at a time.
+    pub fn add_executor(&mut self, executor: Box<dyn Executor<A>>) -> anyhow::Result<()> {
+        self.add_executor_with_concurrency(executor, 1)
+    }
+
+    /// Adds an executor which handles up to `concurrency` actions at once.
+    /// Actions with the same [ordering_key](Executor::ordering_key) are still
+    /// executed one after another, in the order they were received.
+    pub fn add_executor_with_concurrency(
+        &mut self,
+        executor: Box<dyn Executor<A>>,
+        concurrency: usize,
+    ) -> anyhow::Result<()> {
+        assert!(concurrency > 0, "executor concurrency must be at least 1");
+        self.check_unique_name(executor.name())?;
+        self.executors.push((executor, concurrency));
+        Ok(())
+    }
+
+    /// Fails if a component named `name` was already added. Names key the
+    /// health, lag and metrics of each component, its snapshot and its
+    /// control-plane entry, so every instance needs its own; override `name`,
+    /// or wrap the component in a [CollectorMap](crate::types::CollectorMap)
+    /// or [ExecutorMap](crate::types::ExecutorMap) with a name, to tell
+    /// instances of the same type apart.
+    fn check_unique_name(&self, name: &str) -> anyhow::Result<()> {
+        let duplicate = self
+            .collectors
+            .iter()
+            .map(|(collector, _)| collector.name())
+            .chain(self.strategies.iter().map(|strategy| strategy.name()))
+            .chain(self.executors.iter().map(|(executor, _)| executor.name()))
+            .chain([ROUTER_NAME])
+            .any(|existing| existing == name);
+        if duplicate {
+            anyhow::bail!("a component named {} was already added to the engine", name);
+        }
+        Ok(())
+    }
+
+    /// Records every event received from the collectors with the given
+    /// [EventRecorder](EventRecorder). Recorded events can be fed back into an
+    /// engine with a [ReplayCollector](crate::collectors::replay_collector::ReplayCollector).
+    pub fn record_events(&mut self, recorder: EventRecorder)
+    where
+        E: Serialize,
+    {
+        self.recorder = Some(Box::new(move |receiver| {
+            Box::pin(async move {
+                if let Err(e) = recorder.run(receiver).await {
+                    error!("error recording events: {}", e);
+                }
+            })
+        }));
+    }
+
+    /// Publishes an [ExecutionReport](ExecutionReport) for every action an
+    /// executor handles, mapped to an event by `f`, so that strategies learn
+    /// whether their actions landed. Reports for which `f` returns `None` are
+    /// dropped.
+    pub fn report_executions<F>(&mut self, f: F)
+    where
+        F: Fn(ExecutionReport<A>) -> Option<E> + Send + Sync + 'static,
+    {
+        self.reporter = Some(Arc::new(f));
     }
 
     /// Core run loop 引擎
     /// 这个函数将为每个收集器、策略和执行器生成一个线程。
     /// 然后它将协调它们之间的数据流
+    ///
+    /// Once the [ShutdownHandle](ShutdownHandle) is triggered, collectors stop
+    /// first. Strategies then drain the events already queued, and executors
+    /// drain the remaining actions until the shutdown timeout elapses. Every
+    /// task in the returned set completes after its component's teardown hook ran.
     pub async fn run(self) -> Result<JoinSet<()>, Box<dyn std::error::Error>> {
-        let (event_sender, _): (Sender<E>, _) = broadcast::channel(512);
-        let (action_sender, _): (Sender<A>, _) = broadcast::channel(512);
+        let (event_sender, _): (Sender<EventEnvelope<E>>, _) =
+            broadcast::channel(self.event_channel_capacity);
+        let (action_sender, _): (Sender<ActionEnvelope<A>>, _) =
+            broadcast::channel(self.action_channel_capacity);
 
         let mut set = JoinSet::new();
+        let context = TaskContext {
+            shutdown: self.shutdown.clone(),
+            lag_counters: self.lag_counters.clone(),
+            health: self.health.clone(),
+            metrics: self.metrics.clone(),
+            _control: self.control.clone(),
+            resync_policy: self.resync_policy.clone(),
+            snapshots: self.snapshots.clone(),
+        };
+
+        // Executions are reported back as events for as long as collectors run.
+        let reporter = self
+            .reporter
+            .map(|report| ExecutionReporter::new(report, event_sender.clone()));
 
         // 在单独的线程中 启动执行器
-        for executor in self.executors {
-            let mut action_receiver = action_sender.subscribe();                                // 动作接收
-            set.spawn(async move {
-                info!("starting executor... ");
-                loop {
-                    match action_receiver.recv().await {                                        // 接收动作
-                        Ok(action) => match executor.execute(action).await {                    // 执行动作
-                            Ok(_) => {}
-                            Err(e) => error!("error executing action: {}", e),
-                        },
-                        Err(e) => error!("error receiving action: {}", e),
-                    }
-                }
-            });
+        for (executor, concurrency) in self.executors {
+            let action_receiver = action_sender.subscribe(); // 动作接收
+            let reporter = reporter.clone();
+            let shutdown_timeout = self.shutdown_timeout;
+            let paused = self.control.register_executor(executor.name());
+            set.spawn(run_executor(
+                executor,
+                concurrency,
+                paused,
+                action_receiver,
+                reporter,
+                shutdown_timeout,
+                context.clone(),
+            ));
         }
 
         // 在单独的线程中 启动策略
+        let mut routes = vec![];
+        // Events the router drops are reported to every routed strategy.
+        let (router_lag, _) = watch::channel(0u64);
         for mut strategy in self.strategies {
-            let mut event_receiver = event_sender.subscribe();                                  // 事件接收者
-            let action_sender = action_sender.clone();                                          // 动作发送者
-            strategy.sync_state().await?;                                                       // 同步状态
+            // Strategies which filter or prioritise events receive them through the router.
+            let event_receiver = match (strategy.event_filter(), strategy.priority_events()) {
+                // 事件接收者
+                (None, None) => EventLanes::new(event_sender.subscribe(), None),
+                (Some(filter), None) => {
+                    let (route_sender, route_receiver) =
+                        broadcast::channel(self.event_channel_capacity);
+                    routes.push((filter, route_sender));
+                    EventLanes::new(route_receiver, None).with_router_lag(router_lag.subscribe())
+                }
+                (filter, Some(priority)) => {
+                    let filter = filter.unwrap_or_else(|| Arc::new(|_: &E| true));
+                    let (route_sender, route_receiver) =
+                        broadcast::channel(self.event_channel_capacity);
+                    let (priority_sender, priority_receiver) =
+                        broadcast::channel(self.event_channel_capacity);
+                    let (normal_filter, normal_priority) = (filter.clone(), priority.clone());
+                    routes.push((
+                        Arc::new(move |event: &E| filter(event) && priority(event)),
+                        priority_sender,
+                    ));
+                    routes.push((
+                        Arc::new(move |event: &E| normal_filter(event) && !normal_priority(event)),
+                        route_sender,
+                    ));
+                    EventLanes::new(route_receiver, Some(priority_receiver))
+                        .with_router_lag(router_lag.subscribe())
+                }
+            };
+            let action_sender = action_sender.clone(); // 动作发送者
+            let action_sink = ActionSink::new(
+                strategy.name().into(),
+                action_sender.clone(),
+                self.metrics.actions_emitted_counter(strategy.name()),
+            );
+            strategy.set_action_sink(action_sink.clone());
+            if let Some(store) = &self.snapshots {
+                restore_snapshot(strategy.as_mut(), store).await;
+            }
+            strategy.sync_state().await?; // 同步状态
+
+            let control = self.control.register_strategy(strategy.name());
+            set.spawn(run_strategy(
+                strategy,
+                event_receiver,
+                action_sender,
+                action_sink,
+                control,
+                context.clone(),
+            )); // 开始策略
+        }
+
+        // 在单独的线程中 启动事件路由
+        if !routes.is_empty() {
+            set.spawn(route_events(
+                event_sender.subscribe(),
+                routes,
+                router_lag,
+                context.clone(),
+            ));
+        }
 
+        // 在单独的线程中 启动事件记录器
+        if let Some(record) = self.recorder {
+            set.spawn(record(event_sender.subscribe()));
+        }
+
+        // 在单独的线程中 启动收集器
+        let (collectors_running, mut collectors_stopped) = mpsc::channel::<()>(1);
+        for (collector, policy) in self.collectors {
+            // 收集器
+            self.control.register_collector(collector.name());
+            let event_sender = event_sender.clone(); // 事件发送者
+            let running = collectors_running.clone();
+            let context = context.clone();
             set.spawn(async move {
-                info!("starting strategy... ");                                                 // 开始策略
-                loop {
-                    match event_receiver.recv().await {                                         // 接收事件
-                        Ok(event) => {
-                            if let Some(action) = strategy.process_event(event).await {         // 处理事件
-                                match action_sender.send(action) {                              // 发送动作
-                                    Ok(_) => {}
-                                    Err(e) => error!("error sending action: {}", e),
+                // 开始收集器
+                supervise_collector(collector, policy, event_sender, context).await;
+                drop(running);
+            });
+        }
+        drop(collectors_running);
+
+        // Stop reporting executions once every collector has stopped, so that
+        // strategies see the event channel close.
+        if let Some(reporter) = reporter {
+            set.spawn(async move {
+                let _ = collectors_stopped.recv().await;
+                reporter.close();
+            });
+        }
+
+        Ok(set)
+    }
+}
+
+/// Engine state shared with every component task.
+#[derive(Clone)]
+struct TaskContext {
+    shutdown: ShutdownHandle,
+    lag_counters: LagCounters,
+    health: HealthMonitor,
+    metrics: EngineMetrics,
+    // Keeps the control channels of every component open.
+    _control: ControlHandle,
+    resync_policy: RestartPolicy,
+    snapshots: Option<SnapshotStore>,
+}
+
+impl TaskContext {
+    fn set_health(&self, name: &str, health: Health) {
+        self.metrics.set_health(name, &health);
+        self.health.set(name, health);
+    }
+
+    fn record_lag(&self, name: &str, skipped: u64) {
+        self.metrics.lagged(name, skipped);
+        self.lag_counters.record(name, skipped);
+    }
+}
+
+/// Runs a strategy until its event channel closes, forwarding the actions it
+/// returns to the executors. When processing an event fails, the strategy is
+/// marked unhealthy and stops consuming events until it has been resynced.
+/// Events queued in the meantime are processed once the resync completes. A
+/// strategy whose resync is given up on is left unhealthy.
+async fn run_strategy<E, A>(
+    mut strategy: Box<dyn Strategy<E, A>>,
+    mut event_receiver: EventLanes<E>,
+    action_sender: Sender<ActionEnvelope<A>>,
+    action_sink: ActionSink<A>,
+    control: StrategyControl,
+    context: TaskContext,
+) where
+    E: Clone + Send,
+    A: Send,
+{
+    let name = strategy.name().to_string();
+    let origin: Arc<str> = name.as_str().into();
+    info!(strategy = %name, "starting strategy... "); // 开始策略
+    context.set_health(&name, Health::Healthy);
+
+    let StrategyControl {
+        mut paused,
+        mut commands,
+    } = control;
+    let mut snapshot_ticker = context.snapshots.as_ref().map(|store| {
+        let period = store.interval();
+        tokio::time::interval_at(tokio::time::Instant::now() + period, period)
+    });
+    // Set from a resync until the next event is received.
+    let mut resynced = false;
+    let mut failure = None;
+    loop {
+        // A pause is ignored once shutdown is triggered, so that the strategy
+        // drains its queue and stops.
+        let is_paused = *paused.borrow_and_update() && !context.shutdown.is_shutdown();
+        let resync = tokio::select! {
+            biased;
+            Some(command) = commands.recv() => match command {
+                StrategyCommand::Resync => {
+                    info!(strategy = %name, "resync requested");
+                    true
+                }
+                StrategyCommand::Summary(reply) => {
+                    let _ = reply.send(strategy.state_summary());
+                    false
+                }
+            },
+            _ = paused.changed() => {
+                info!(strategy = %name, paused = *paused.borrow(), "strategy pause toggled");
+                false
+            }
+            _ = next_tick(&mut snapshot_ticker) => {
+                save_snapshot(strategy.as_ref(), &name, &context).await;
+                false
+            }
+            _ = context.shutdown.wait(), if is_paused => {
+                info!(strategy = %name, "shutting down, resuming paused strategy");
+                false
+            }
+            result = event_receiver.recv(), if !is_paused => match result {                 // 接收事件
+                Ok(envelope) => {
+                    resynced = false;
+                    let span = info_span!("event", id = %envelope.id, source = %envelope.source, strategy = %name);
+                    async {
+                        let started = Instant::now();
+                        let result = strategy.process_event(envelope.event).await;      // 处理事件
+                        context.metrics.event_processed(&name, started.elapsed().as_secs_f64());
+                        match result {
+                            Ok(actions) => {
+                                context.metrics.actions_emitted(&name, actions.len());
+                                for action in actions {
+                                    let action = ActionEnvelope {
+                                        id: ActionId::next(),
+                                        strategy: origin.clone(),
+                                        event_id: Some(envelope.id),
+                                        event_received_at: Some(envelope.received_at),
+                                        deadline: strategy.action_deadline(&action),
+                                        action,
+                                    };
+                                    debug!(action_id = %action.id, "strategy emitted action");
+                                    match action_sender.send(action) {                  // 发送动作
+                                        Ok(_) => {}
+                                        Err(e) => error!("error sending action: {}", e),
+                                    }
                                 }
+                                false
+                            }
+                            Err(e) => {
+                                error!("error processing event: {:#}", e);
+                                context.set_health(&name, Health::Unhealthy(e.to_string()));
+                                true
                             }
                         }
-                        Err(e) => error!("error receiving event: {}", e),
                     }
+                    .instrument(span)
+                    .await
                 }
-            });
+                // All collectors have stopped and the queue is drained.
+                Err(RecvError::Closed) => break,
+                // The oldest events queued while resyncing were dropped, and
+                // are likely superseded by the fresh state.
+                Err(RecvError::Lagged(skipped)) if resynced => {
+                    context.record_lag(&name, skipped);
+                    warn!(strategy = %name, skipped, "events were dropped while the strategy resynced");
+                    false
+                }
+                Err(RecvError::Lagged(skipped)) => {
+                    context.record_lag(&name, skipped);
+                    warn!(strategy = %name, skipped, "strategy lagged, events were dropped");
+                    strategy.on_lag(skipped) == LagPolicy::Resync
+                }
+            },
+        };
+
+        if resync {
+            context.metrics.strategy_resynced(&name);
+            match resync_strategy(strategy.as_mut(), &name, &context).await {
+                Ok(true) => {}
+                Ok(false) => break,
+                Err(e) => {
+                    failure = Some(e);
+                    break;
+                }
+            }
+            // Events queued during the resync are still processed: the fresh
+            // state does not include the opportunities they carry.
+            resynced = true;
+            context.set_health(&name, Health::Healthy);
+        }
+    }
+
+    save_snapshot(strategy.as_ref(), &name, &context).await;
+    if let Err(e) = strategy.teardown().await {
+        error!("error tearing down strategy: {}", e);
+    }
+    action_sink.close();
+    match failure {
+        Some(e) => {
+            context.set_health(&name, Health::Unhealthy(format!("resync failed: {:#}", e)));
+            error!(strategy = %name, "strategy stopped after failing to resync");
         }
+        None => {
+            context.set_health(&name, Health::Stopped);
+            info!(strategy = %name, "strategy stopped");
+        }
+    }
+}
 
-        // 在单独的线程中 启动收集器
-        for collector in self.collectors {                                              // 收集器
-            let event_sender = event_sender.clone();                                    // 事件发送者
-            set.spawn(async move {                                                      // 开始收集器
-                info!("starting collector... ");
-                let mut event_stream = collector.get_event_stream().await.unwrap();     // 获取事件流
-                while let Some(event) = event_stream.next().await {                     // 获取事件
-                    match event_sender.send(event) {                                    // 发送事件
-                        Ok(_) => {}
-                        Err(e) => error!("error sending event: {}", e),
+/// Waits for the next tick of the ticker, or forever if there is none.
+async fn next_tick(ticker: &mut Option<tokio::time::Interval>) {
+    match ticker {
+        Some(ticker) => {
+            ticker.tick().await;
+        }
+        None => std::future::pending().await,
+    }
+}
+
+/// Restores a strategy from its latest snapshot, if there is one. Failures are
+/// logged, leaving [sync_state](Strategy::sync_state) to sync from scratch.
+async fn restore_snapshot<E, A>(strategy: &mut dyn Strategy<E, A>, store: &SnapshotStore) {
+    let name = strategy.name().to_string();
+    match store.load(&name).await {
+        Ok(Some(snapshot)) => match strategy.restore(snapshot) {
+            Ok(()) => info!(strategy = %name, "restored strategy snapshot"),
+            Err(e) => warn!(strategy = %name, "error restoring strategy snapshot: {:#}", e),
+        },
+        Ok(None) => info!(strategy = %name, "no strategy snapshot to restore"),
+        Err(e) => warn!(strategy = %name, "error loading strategy snapshot: {:#}", e),
+    }
+}
+
+/// Persists a snapshot of the strategy, if snapshots are enabled and the
+/// strategy supports them.
+async fn save_snapshot<E, A>(strategy: &dyn Strategy<E, A>, name: &str, context: &TaskContext) {
+    let Some(store) = &context.snapshots else {
+        return;
+    };
+    match strategy.snapshot() {
+        Ok(Some(snapshot)) => match store.save(name, &snapshot).await {
+            Ok(()) => debug!(strategy = %name, "saved strategy snapshot"),
+            Err(e) => error!(strategy = %name, "error saving strategy snapshot: {:#}", e),
+        },
+        Ok(None) => {}
+        Err(e) => error!(strategy = %name, "error taking strategy snapshot: {:#}", e),
+    }
+}
+
+/// The channels on which a strategy receives events: an optional priority lane,
+/// which is always drained first, and a lane for every other event. Routed
+/// strategies also watch the number of events the router dropped, so they
+/// see its lag like their own.
+struct EventLanes<E> {
+    normal: Receiver<EventEnvelope<E>>,
+    priority: Option<Receiver<EventEnvelope<E>>>,
+    router_lag: Option<watch::Receiver<u64>>,
+    router_lag_seen: u64,
+}
+
+impl<E: Clone> EventLanes<E> {
+    fn new(
+        normal: Receiver<EventEnvelope<E>>,
+        priority: Option<Receiver<EventEnvelope<E>>>,
+    ) -> Self {
+        Self {
+            normal,
+            priority,
+            router_lag: None,
+            router_lag_seen: 0,
+        }
+    }
+
+    /// Reports the events dropped by the router, a running total, as lag.
+    fn with_router_lag(mut self, router_lag: watch::Receiver<u64>) -> Self {
+        self.router_lag = Some(router_lag);
+        self
+    }
+
+    /// Receives the next event, taking queued priority events first. Events
+    /// dropped by the router since the last call are returned as lag.
+    async fn recv(&mut self) -> Result<EventEnvelope<E>, RecvError> {
+        loop {
+            if let Some(router_lag) = &mut self.router_lag {
+                let dropped = *router_lag.borrow_and_update();
+                if dropped > self.router_lag_seen {
+                    let skipped = dropped - self.router_lag_seen;
+                    self.router_lag_seen = dropped;
+                    return Err(RecvError::Lagged(skipped));
+                }
+            }
+            tokio::select! {
+                biased;
+                _ = router_lag_changed(&mut self.router_lag) => {}
+                result = recv_priority(&mut self.priority) => match result {
+                    // The lanes close together, so keep draining the normal lane.
+                    Err(RecvError::Closed) => self.priority = None,
+                    result => return result,
+                },
+                result = self.normal.recv() => return result,
+            }
+        }
+    }
+}
+
+/// Receives from the priority lane; never resolves without one.
+async fn recv_priority<E: Clone>(
+    priority: &mut Option<Receiver<EventEnvelope<E>>>,
+) -> Result<EventEnvelope<E>, RecvError> {
+    match priority {
+        Some(priority) => priority.recv().await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Resolves when the router reports more dropped events; never resolves for
+/// unrouted strategies or once the router stopped.
+async fn router_lag_changed(router_lag: &mut Option<watch::Receiver<u64>>) {
+    if let Some(router_lag) = router_lag {
+        if router_lag.changed().await.is_ok() {
+            return;
+        }
+    }
+    std::future::pending().await
+}
+
+/// Forwards each event to the strategies whose filter accepts it, until the
+/// event channel closes.
+async fn route_events<E>(
+    mut event_receiver: Receiver<EventEnvelope<E>>,
+    routes: Vec<(EventFilter<E>, Sender<EventEnvelope<E>>)>,
+    router_lag: watch::Sender<u64>,
+    context: TaskContext,
+) where
+    E: Clone + Send,
+{
+    let name = ROUTER_NAME;
+    loop {
+        match event_receiver.recv().await {
+            Ok(envelope) => {
+                for (filter, route_sender) in &routes {
+                    // A route without receivers belongs to a strategy that stopped.
+                    if filter(&envelope.event) && route_sender.send(envelope.clone()).is_err() {
+                        debug!(event_id = %envelope.id, "no strategy is receiving routed event");
                     }
                 }
-            });
+            }
+            Err(RecvError::Closed) => break,
+            Err(RecvError::Lagged(skipped)) => {
+                context.record_lag(name, skipped);
+                warn!(skipped, "event router lagged, events were dropped");
+                // The dropped events may have matched any route.
+                router_lag.send_modify(|dropped| *dropped += skipped);
+            }
         }
+    }
+}
 
-        Ok(set)
+/// Runs an executor until its action channel closes, or until the shutdown
+/// deadline elapses.
+async fn run_executor<E, A>(
+    executor: Box<dyn Executor<A>>,
+    concurrency: usize,
+    mut paused: watch::Receiver<bool>,
+    mut action_receiver: Receiver<ActionEnvelope<A>>,
+    reporter: Option<ExecutionReporter<E, A>>,
+    shutdown_timeout: Duration,
+    context: TaskContext,
+) where
+    E: Send + 'static,
+    A: Clone + Send + 'static,
+{
+    let executor: Arc<dyn Executor<A>> = executor.into();
+    let name: Arc<str> = executor.name().into();
+    info!(executor = %name, concurrency, "starting executor... ");
+    context.set_health(&name, Health::Healthy);
+
+    let process_actions = async {
+        let permits = Arc::new(Semaphore::new(concurrency));
+        let mut in_flight = JoinSet::new();
+        // Dropped once the last action queued for each ordering key completes
+        // or releases its key.
+        let mut ordering_tails: HashMap<String, oneshot::Receiver<()>> = HashMap::new();
+        loop {
+            let is_paused = *paused.borrow_and_update();
+            let result = tokio::select! {
+                biased;
+                _ = paused.changed() => {
+                    info!(executor = %name, paused = *paused.borrow(), "executor pause toggled");
+                    continue;
+                }
+                result = action_receiver.recv(), if !is_paused => result,              // 接收动作
+            };
+            match result {
+                Ok(envelope) => {
+                    let permit = permits
+                        .clone()
+                        .acquire_owned()
+                        .await
+                        .expect("semaphore is never closed");
+                    while let Some(Some(_)) = in_flight.join_next().now_or_never() {}
+
+                    // Actions sharing an ordering key wait for the previous one.
+                    let key = if concurrency > 1 {
+                        executor.ordering_key(&envelope.action)
+                    } else {
+                        None
+                    };
+                    let (previous, done) = match key {
+                        Some(key) => {
+                            ordering_tails.retain(|_, tail| {
+                                matches!(tail.try_recv(), Err(TryRecvError::Empty))
+                            });
+                            let (done, tail) = oneshot::channel::<()>();
+                            (ordering_tails.insert(key, tail), Some(done))
+                        }
+                        None => (None, None),
+                    };
+
+                    let span = info_span!(
+                        "action",
+                        id = %envelope.id,
+                        strategy = %envelope.strategy,
+                        event_id = envelope.event_id.map(|id| id.0),
+                        executor = %name,
+                    );
+                    let executor = executor.clone();
+                    let name = name.clone();
+                    let reporter = reporter.clone();
+                    let metrics = context.metrics.clone();
+                    in_flight.spawn(
+                        async move {
+                            let _permit = permit;
+                            if let Some(previous) = previous {
+                                let _ = previous.await;
+                            }
+                            let action = execute_action(
+                                executor.as_ref(),
+                                &name,
+                                envelope,
+                                reporter.as_ref(),
+                                &metrics,
+                            );
+                            hold_ordering(done, action).await;
+                        }
+                        .instrument(span),
+                    );
+                }
+                Err(RecvError::Closed) => break,
+                Err(RecvError::Lagged(skipped)) => {
+                    context.record_lag(&name, skipped);
+                    error!(executor = %name, skipped, "executor lagged, actions were dropped");
+                }
+            }
+        }
+        while let Some(result) = in_flight.join_next().await {
+            if let Err(e) = result {
+                error!(executor = %name, "action task failed: {}", e);
+            }
+        }
+    };
+
+    // Dropping in-flight actions at the deadline aborts them.
+    tokio::select! {
+        _ = process_actions => {}
+        _ = context.shutdown.deadline(shutdown_timeout) => {
+            warn!(executor = %name, "executor did not finish in-flight actions before the shutdown deadline");
+        }
+    }
+
+    if let Err(e) = executor.teardown().await {
+        error!("error tearing down executor: {}", e);
+    }
+    context.set_health(&name, Health::Stopped);
+    info!(executor = %name, "executor stopped");
+}
+
+/// Executes a single action, unless its deadline has passed, and reports the
+/// outcome.
+async fn execute_action<E, A>(
+    executor: &dyn Executor<A>,
+    name: &str,
+    envelope: ActionEnvelope<A>,
+    reporter: Option<&ExecutionReporter<E, A>>,
+    metrics: &EngineMetrics,
+) where
+    A: Clone + Send,
+{
+    if let Some(deadline) = &envelope.deadline {
+        if executor.is_expired(deadline).await {
+            warn!(?deadline, "dropping action, its deadline has passed");
+            metrics.action_executed(name, ExecutionOutcome::Expired, None);
+            return;
+        }
+    }
+    if let Some(received_at) = envelope.event_received_at {
+        let age_ms = received_at.elapsed().unwrap_or_default().as_millis() as u64;
+        debug!(age_ms, "executing action");
+    }
+
+    let reported_action = reporter.map(|_| envelope.action.clone());
+    let started = Instant::now();
+    let result = executor.execute(envelope.action).await; // 执行动作
+    let elapsed = Some(started.elapsed().as_secs_f64());
+    match &result {
+        Ok(Some(execution)) => {
+            debug!(tx_hash = ?execution.tx_hash, "action executed");
+            metrics.action_executed(name, ExecutionOutcome::Success, elapsed);
+        }
+        Ok(None) => metrics.action_executed(name, ExecutionOutcome::Ignored, None),
+        Err(e) => {
+            error!("error executing action: {}", e);
+            metrics.action_executed(name, ExecutionOutcome::Failure, elapsed);
+        }
+    }
+    if let (Some(reporter), Some(action)) = (reporter, reported_action) {
+        reporter.report(name, envelope.id, action, result);
+    }
+}
+
+/// Publishes [ExecutionReport](ExecutionReport)s to the event channel, mapped
+/// to the engine's event type.
+struct ExecutionReporter<E, A> {
+    report: ReportFn<E, A>,
+    event_sender: SharedSender<EventEnvelope<E>>,
+}
+
+impl<E, A> ExecutionReporter<E, A> {
+    fn new(report: ReportFn<E, A>, event_sender: Sender<EventEnvelope<E>>) -> Self {
+        Self {
+            report,
+            event_sender: Arc::new(RwLock::new(Some(event_sender))),
+        }
+    }
+
+    /// Publishes the outcome of an action, unless reporting has been closed.
+    fn report(
+        &self,
+        executor: &str,
+        action_id: ActionId,
+        action: A,
+        result: anyhow::Result<Option<Execution>>,
+    ) {
+        let result = match result {
+            Ok(Some(execution)) => Ok(execution),
+            // The executor ignored the action.
+            Ok(None) => return,
+            Err(e) => Err(format!("{:#}", e)),
+        };
+        let report = ExecutionReport {
+            action_id,
+            executor: executor.to_string(),
+            action,
+            result,
+        };
+        let Some(event) = (self.report)(report) else {
+            return;
+        };
+        if let Some(event_sender) = self.event_sender.read().unwrap().as_ref() {
+            let envelope = EventEnvelope {
+                id: EventId::next(),
+                source: executor.into(),
+                received_at: SystemTime::now(),
+                execution_report: true,
+                event,
+            };
+            if let Err(e) = event_sender.send(envelope) {
+                error!("error sending execution report: {}", e);
+            }
+        }
+    }
+
+    fn close(&self) {
+        self.event_sender.write().unwrap().take();
+    }
+}
+
+impl<E, A> Clone for ExecutionReporter<E, A> {
+    fn clone(&self) -> Self {
+        Self {
+            report: self.report.clone(),
+            event_sender: self.event_sender.clone(),
+        }
+    }
+}
+
+/// Calls [sync_state](Strategy::sync_state) until it succeeds, backing off
+/// between attempts. Returns false if the engine shuts down first, and the
+/// last error once the resync policy gives up.
+async fn resync_strategy<E, A>(
+    strategy: &mut dyn Strategy<E, A>,
+    name: &str,
+    context: &TaskContext,
+) -> anyhow::Result<bool> {
+    let policy = &context.resync_policy;
+    let mut attempts = 0;
+    loop {
+        info!(strategy = %name, attempts, "resyncing strategy state");
+        let e = match strategy.sync_state().await {
+            Ok(_) => {
+                info!(strategy = %name, "strategy resynced");
+                return Ok(true);
+            }
+            Err(e) => e,
+        };
+
+        if policy.max_restarts.is_some_and(|max| attempts >= max) {
+            error!(strategy = %name, attempts, "strategy exceeded its resync limit: {:#}", e);
+            return Err(e);
+        }
+        let backoff = policy.backoff(attempts);
+        attempts += 1;
+        warn!(
+            strategy = %name,
+            attempts,
+            backoff_ms = backoff.as_millis() as u64,
+            "error resyncing strategy: {:#}",
+            e
+        );
+
+        tokio::select! {
+            _ = context.shutdown.wait() => return Ok(false),
+            _ = tokio::time::sleep(backoff) => {}
+        }
+    }
+}
+
+/// Runs a collector until shutdown, forwarding its events to the event channel.
+/// When the event stream fails or ends, the collector is restarted according to
+/// its [RestartPolicy](RestartPolicy).
+async fn supervise_collector<E>(
+    collector: Box<dyn Collector<E>>,
+    policy: RestartPolicy,
+    event_sender: Sender<EventEnvelope<E>>,
+    context: TaskContext,
+) {
+    let name = collector.name().to_string();
+    let source: Arc<str> = name.as_str().into();
+    info!(collector = %name, "starting collector... ");
+
+    let stopped = context.shutdown.wait();
+    tokio::pin!(stopped);
+
+    // Number of restarts since the collector last produced an event.
+    let mut restarts = 0;
+    // Why the collector failed for the last time, once it exceeded its
+    // restart limit.
+    let mut failure = None;
+    'supervise: loop {
+        let reason = match collector.get_event_stream().await {
+            // 获取事件流
+            Ok(mut event_stream) => {
+                if restarts > 0 {
+                    info!(collector = %name, restarts, "collector restarted");
+                    context.metrics.collector_restarted(&name);
+                }
+                context.set_health(&name, Health::Healthy);
+                loop {
+                    tokio::select! {
+                        _ = &mut stopped => break 'supervise,
+                        event = event_stream.next() => match event {                    // 获取事件
+                            Some(event) => {
+                                restarts = 0;
+                                context.metrics.event_received(&name);
+                                let envelope = EventEnvelope {
+                                    id: EventId::next(),
+                                    source: source.clone(),
+                                    received_at: SystemTime::now(),
+                                    execution_report: false,
+                                    event,
+                                };
+                                debug!(collector = %name, event_id = %envelope.id, "received event");
+                                match event_sender.send(envelope) {                     // 发送事件
+                                    Ok(_) => {}
+                                    Err(e) => error!("error sending event: {}", e),
+                                }
+                            }
+                            None => break "event stream ended".to_string(),
+                        },
+                    }
+                }
+            }
+            Err(e) => format!("error getting event stream: {}", e),
+        };
+
+        if policy.max_restarts.is_some_and(|max| restarts >= max) {
+            error!(collector = %name, restarts, reason = %reason, "collector exceeded its restart limit");
+            failure = Some(reason);
+            break;
+        }
+        context.set_health(&name, Health::Unhealthy(reason.clone()));
+        let backoff = policy.backoff(restarts);
+        restarts += 1;
+        warn!(
+            collector = %name,
+            restarts,
+            backoff_ms = backoff.as_millis() as u64,
+            reason = %reason,
+            "restarting collector"
+        );
+
+        tokio::select! {
+            _ = &mut stopped => break,
+            _ = tokio::time::sleep(backoff) => {}
+        }
+    }
+    match failure {
+        Some(reason) => {
+            context.set_health(&name, Health::Unhealthy(reason));
+            error!(collector = %name, "collector stopped after exceeding its restart limit");
+        }
+        None => {
+            context.set_health(&name, Health::Stopped);
+            info!(collector = %name, "collector stopped");
+        }
+    }
+}
+
+/// Policy used by the engine to restart a collector whose event stream failed
+/// or ended, and to retry resyncing a failed strategy. Attempts are delayed
+/// with exponential backoff.
+#[derive(Debug, Clone)]
+pub struct RestartPolicy {
+    /// Maximum number of consecutive restarts, or `None` to restart forever.
+    /// For collectors, the count resets whenever the collector emits an event.
+    pub max_restarts: Option<u32>,
+
+    /// Delay before the first restart.
+    pub initial_backoff: Duration,
+
+    /// Upper bound on the delay between restarts.
+    pub max_backoff: Duration,
+
+    /// Factor by which the delay grows after each consecutive restart.
+    pub multiplier: u32,
+}
+
+impl RestartPolicy {
+    /// A policy that never restarts the component.
+    pub fn never() -> Self {
+        Self {
+            max_restarts: Some(0),
+            ..Self::default()
+        }
+    }
+
+    /// Returns the delay before the restart following `restarts` consecutive restarts.
+    fn backoff(&self, restarts: u32) -> Duration {
+        let factor = self.multiplier.saturating_pow(restarts);
+        self.initial_backoff
+            .saturating_mul(factor)
+            .min(self.max_backoff)
+    }
+}
+
+impl Default for RestartPolicy {
+    fn default() -> Self {
+        Self {
+            max_restarts: None,
+            initial_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(60),
+            multiplier: 2,
+        }
+    }
+}
+
+/// Counts the messages each strategy and executor missed because it fell
+/// behind its channel, keyed by component name. Counters are cheap to clone and
+/// can be read while the engine is running.
+#[derive(Debug, Clone, Default)]
+pub struct LagCounters {
+    counts: Arc<Mutex<HashMap<String, u64>>>,
+}
+
+impl LagCounters {
+    /// Returns the number of messages missed by the given component.
+    pub fn get(&self, name: &str) -> u64 {
+        self.counts
+            .lock()
+            .unwrap()
+            .get(name)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Returns the number of messages missed by every component that lagged.
+    pub fn snapshot(&self) -> HashMap<String, u64> {
+        self.counts.lock().unwrap().clone()
+    }
+
+    fn record(&self, name: &str, skipped: u64) {
+        *self
+            .counts
+            .lock()
+            .unwrap()
+            .entry(name.to_string())
+            .or_default() += skipped;
+    }
+}
+
+/// Health of a component managed by the engine.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub enum Health {
+    /// The component is running normally.
+    Healthy,
+    /// The component failed and is being restarted or resynced, or it was
+    /// stopped after exceeding its restart or resync limit.
+    Unhealthy(String),
+    /// The component has stopped.
+    Stopped,
+}
+
+/// Tracks the [Health](Health) of every component, keyed by component name.
+/// Monitors are cheap to clone and can be read while the engine is running.
+#[derive(Debug, Clone, Default)]
+pub struct HealthMonitor {
+    statuses: Arc<Mutex<HashMap<String, Health>>>,
+}
+
+impl HealthMonitor {
+    /// Returns the health of the given component, if it has started.
+    pub fn get(&self, name: &str) -> Option<Health> {
+        self.statuses.lock().unwrap().get(name).cloned()
+    }
+
+    /// Returns the health of every component that has started.
+    pub fn snapshot(&self) -> HashMap<String, Health> {
+        self.statuses.lock().unwrap().clone()
+    }
+
+    fn set(&self, name: &str, health: Health) {
+        self.statuses
+            .lock()
+            .unwrap()
+            .insert(name.to_string(), health);
+    }
+}
+
+/// A handle used to gracefully shut down a running [Engine](Engine). Handles
+/// are cheap to clone, and shutdown can be triggered from any of them.
+#[derive(Debug, Clone)]
+pub struct ShutdownHandle {
+    sender: Arc<watch::Sender<bool>>,
+}
+
+impl ShutdownHandle {
+    fn new() -> Self {
+        let (sender, _) = watch::channel(false);
+        Self {
+            sender: Arc::new(sender),
+        }
+    }
+
+    /// Triggers the shutdown of the engine.
+    pub fn shutdown(&self) {
+        self.sender.send_replace(true);
+    }
+
+    /// Returns true if shutdown has been triggered.
+    pub fn is_shutdown(&self) -> bool {
+        *self.sender.borrow()
+    }
+
+    /// Waits until shutdown has been triggered.
+    pub async fn wait(&self) {
+        let mut receiver = self.sender.subscribe();
+        while !*receiver.borrow_and_update() {
+            if receiver.changed().await.is_err() {
+                return;
+            }
+        }
+    }
+
+    /// Triggers shutdown once the process receives SIGINT or SIGTERM.
+    pub async fn shutdown_on_signal(self) -> std::io::Result<()> {
+        #[cfg(unix)]
+        {
+            use tokio::signal::unix::{signal, SignalKind};
+            let mut terminate = signal(SignalKind::terminate())?;
+            tokio::select! {
+                res = tokio::signal::ctrl_c() => res?,
+                _ = terminate.recv() => {}
+            }
+        }
+        #[cfg(not(unix))]
+        tokio::signal::ctrl_c().await?;
+
+        info!("received shutdown signal");
+        self.shutdown();
+        Ok(())
+    }
+
+    /// Completes `timeout` after shutdown has been triggered.
+    async fn deadline(&self, timeout: Duration) {
+        self.wait().await;
+        tokio::time::sleep(timeout).await;
     }
 }
@@ -1,20 +1,15 @@
 use std::sync::Arc;
 
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use async_trait::async_trait;
 use ethers::{
-    providers::Middleware, 
-    signers::Signer, 
-    types::transaction::eip2718::TypedTransaction,
-};
-use ethers_flashbots::{
-    BundleRequest, 
-    FlashbotsMiddleware
+    providers::Middleware, signers::Signer, types::transaction::eip2718::TypedTransaction,
 };
+use ethers_flashbots::{BundleRequest, FlashbotsMiddleware};
 use reqwest::Url;
-use tracing::error;
+use tracing::{error, info};
 
-use crate::types::Executor;
+use crate::types::{Deadline, Execution, Executor};
 
 /// A Flashbots executor that sends transactions to the Flashbots relay.
 /// 一个将交易发送到 Flashbots 中继的 Flashbots 执行器
@@ -49,9 +44,10 @@ where
     M::Error: 'static,
     S: Signer + 'static,
 {
-    /// Send a bundle to transactions to the Flashbots relay.
+    /// Send a bundle to transactions to the Flashbots relay. Fails if the
+    /// bundle does not simulate cleanly, or if the relay rejects it.
     /// 将交易包发送到 Flashbots 中继
-    async fn execute(&self, action: FlashbotsBundle) -> Result<()> {
+    async fn execute(&self, action: FlashbotsBundle) -> Result<Option<Execution>> {
         // Add txs to bundle.
         // 将交易添加到交易包
         let mut bundle = BundleRequest::new();
@@ -71,19 +67,54 @@ where
             .set_simulation_block(block_number)
             .set_simulation_timestamp(0);
 
-        let simulated_bundle = self.fb_client.simulate_bundle(&bundle).await;
+        let simulated_bundle = self
+            .fb_client
+            .simulate_bundle(&bundle)
+            .await
+            .map_err(|e| anyhow!("error simulating bundle: {:?}", e))?;
 
-        if let Err(simulate_error) = simulated_bundle {
-            error!("Error simulating bundle: {:?}", simulate_error);
+        // Don't send bundles with a failing transaction.
+        // 不发送包含失败交易的交易包
+        for tx in &simulated_bundle.transactions {
+            if let Some(reason) = tx.error.as_ref().or(tx.revert.as_ref()) {
+                return Err(anyhow!(
+                    "bundle transaction {:?} failed in simulation: {}",
+                    tx.hash,
+                    reason
+                ));
+            }
         }
 
         // Send bundle.
-        let pending_bundle = self.fb_client.send_bundle(&bundle).await;
+        // 发送交易包
+        let pending_bundle = self
+            .fb_client
+            .send_bundle(&bundle)
+            .await
+            .map_err(|e| anyhow!("error sending bundle: {:?}", e))?;
+        info!(bundle_hash = ?pending_bundle.bundle_hash, block = %(block_number + 1), "sent bundle");
 
-        if let Err(send_error) = pending_bundle {
-            error!("Error sending bundle: {:?}", send_error);
-        }
+        Ok(Some(Execution {
+            gas_used: Some(simulated_bundle.gas_used),
+            bundle_hash: pending_bundle.bundle_hash,
+            bundle_tx_hashes: bundle.transaction_hashes(),
+            ..Execution::default()
+        }))
+    }
 
-        Ok(())
+    /// Check the deadline against the latest block.
+    /// 根据最新区块检查截止时间
+    async fn is_expired(&self, deadline: &Deadline) -> bool {
+        let current_block = match deadline {
+            Deadline::Block(_) => match self.fb_client.get_block_number().await {
+                Ok(block) => Some(block),
+                Err(e) => {
+                    error!("Error getting block number: {:?}", e);
+                    None
+                }
+            },
+            Deadline::Instant(_) => None,
+        };
+        deadline.has_passed(current_block)
     }
 }
@@ -1,23 +1,38 @@
 use std::{
     ops::{Div, Mul},
     sync::Arc,
+    time::Duration,
 };
 
-use crate::types::Executor;
-use anyhow::{Context, Result};
+use crate::types::{release_ordering, Deadline, Execution, Executor};
+use anyhow::{anyhow, Context, Result};
 use async_trait::async_trait;
 use ethers::{
     providers::Middleware,
-    types::{transaction::eip2718::TypedTransaction, U256},
+    types::{transaction::eip2718::TypedTransaction, U256, U64},
 };
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+/// Default amount of time to wait for a transaction to be included.
+const DEFAULT_RECEIPT_TIMEOUT: Duration = Duration::from_secs(36);
 
 /// An executor that sends transactions to the mempool.
 pub struct MempoolExecutor<M> {
     client: Arc<M>,
+
+    /// Whether to wait for transactions to be included before returning.
+    wait_for_receipt: bool,
+
+    /// How long to wait for a transaction to be included.
+    receipt_timeout: Duration,
+
+    /// Whether transactions from the same sender must be sent one at a time.
+    order_by_sender: bool,
 }
 
 /// Information about the gas bid for a transaction.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct GasBidInfo {
     /// Total profit expected from opportunity
     pub total_profit: U256,
@@ -26,7 +41,7 @@ pub struct GasBidInfo {
     pub bid_percentage: u64,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SubmitTxToMempool {
     pub tx: TypedTransaction,
     pub gas_bid_info: Option<GasBidInfo>,
@@ -34,7 +49,38 @@ pub struct SubmitTxToMempool {
 
 impl<M: Middleware> MempoolExecutor<M> {
     pub fn new(client: Arc<M>) -> Self {
-        Self { client }
+        Self {
+            client,
+            wait_for_receipt: false,
+            receipt_timeout: DEFAULT_RECEIPT_TIMEOUT,
+            order_by_sender: false,
+        }
+    }
+
+    /// Wait for each transaction to be included, so that the reported
+    /// [Execution](Execution) carries its block, gas used and revert status.
+    /// The engine counts the wait against the executor's concurrency.
+    pub fn with_receipts(mut self, wait_for_receipt: bool) -> Self {
+        self.wait_for_receipt = wait_for_receipt;
+        self
+    }
+
+    /// Sets how long to wait for a transaction to be included. Transactions
+    /// still pending after the timeout are reported without a block number.
+    pub fn with_receipt_timeout(mut self, receipt_timeout: Duration) -> Self {
+        self.receipt_timeout = receipt_timeout;
+        self
+    }
+
+    /// Send transactions from the same sender one at a time, in the order they
+    /// were received, when the engine runs this executor concurrently.
+    /// Transactions without a sender are ordered with the client's default
+    /// sender, as that is the account which signs them. Only sending is
+    /// ordered: the next transaction is sent without waiting for the receipt
+    /// of the previous one.
+    pub fn with_sender_ordering(mut self, order_by_sender: bool) -> Self {
+        self.order_by_sender = order_by_sender;
+        self
     }
 }
 
@@ -45,7 +91,7 @@ where
     M::Error: 'static,
 {
     /// Send a transaction to the mempool.
-    async fn execute(&self, mut action: SubmitTxToMempool) -> Result<()> {
+    async fn execute(&self, mut action: SubmitTxToMempool) -> Result<Option<Execution>> {
         let gas_usage = self
             .client
             .estimate_gas(&action.tx, None)
@@ -68,7 +114,68 @@ where
                 .context("Error getting gas price: {}")?;
         }
         action.tx.set_gas_price(bid_gas_price);
-        self.client.send_transaction(action.tx, None).await?;
-        Ok(())
+        let pending_tx = self.client.send_transaction(action.tx, None).await?;
+        let tx_hash = pending_tx.tx_hash();
+        // The nonce is assigned, so the next transaction can be sent.
+        release_ordering();
+        if !self.wait_for_receipt {
+            return Ok(Some(Execution {
+                tx_hash: Some(tx_hash),
+                ..Execution::default()
+            }));
+        }
+
+        let receipt = match tokio::time::timeout(self.receipt_timeout, pending_tx).await {
+            Ok(receipt) => receipt?
+                .ok_or_else(|| anyhow!("transaction {:?} was dropped from the mempool", tx_hash))?,
+            Err(_) => {
+                warn!(
+                    "transaction {:?} was not included within {:?}",
+                    tx_hash, self.receipt_timeout
+                );
+                return Ok(Some(Execution {
+                    tx_hash: Some(tx_hash),
+                    ..Execution::default()
+                }));
+            }
+        };
+        Ok(Some(Execution {
+            tx_hash: Some(tx_hash),
+            block_number: receipt.block_number,
+            gas_used: receipt.gas_used,
+            reverted: receipt.status == Some(U64::zero()),
+            ..Execution::default()
+        }))
+    }
+
+    /// Order transactions by sender, if enabled.
+    fn ordering_key(&self, action: &SubmitTxToMempool) -> Option<String> {
+        if !self.order_by_sender {
+            return None;
+        }
+        action
+            .tx
+            .from()
+            .copied()
+            .or_else(|| self.client.default_sender())
+            .map(|from| format!("{:?}", from))
+    }
+
+    /// Check the deadline against the latest block.
+    async fn is_expired(&self, deadline: &Deadline) -> bool {
+        let current_block = match deadline {
+            Deadline::Block(_) => match self.client.get_block_number().await {
+                Ok(block) => Some(block),
+                Err(e) => {
+                    warn!(
+                        "error getting block number, assuming deadline has not passed: {}",
+                        e
+                    );
+                    None
+                }
+            },
+            Deadline::Instant(_) => None,
+        };
+        deadline.has_passed(current_block)
     }
 }
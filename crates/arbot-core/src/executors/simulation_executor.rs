@@ -0,0 +1,322 @@
+use std::{
+    collections::VecDeque,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use ethers::{
+    abi::AbiDecode,
+    providers::{Middleware, MiddlewareError},
+    types::{
+        transaction::eip2718::TypedTransaction, Address, BlockId, BlockNumber, Diff, DiffMode,
+        GethDebugBuiltInTracerConfig, GethDebugBuiltInTracerType, GethDebugTracerConfig,
+        GethDebugTracerType, GethDebugTracingCallOptions, GethDebugTracingOptions, GethTrace,
+        GethTraceFrame, PreStateConfig, PreStateFrame, TraceType, I256, U256,
+    },
+};
+use tracing::{debug, info, warn};
+
+use crate::executors::{flashbots_executor::FlashbotsBundle, mempool_executor::SubmitTxToMempool};
+use crate::types::{Deadline, Execution, Executor};
+
+/// Selector of the `Error(string)` revert payload.
+const REVERT_SELECTOR: [u8; 4] = [0x08, 0xc3, 0x79, 0xa0];
+
+/// Default number of recent simulation outcomes kept in memory.
+const DEFAULT_OUTCOME_CAPACITY: usize = 1_000;
+
+/// An executor that simulates transactions against the pending block instead
+/// of sending them. It accepts the same actions as the
+/// [MempoolExecutor](crate::executors::mempool_executor::MempoolExecutor) and
+/// the [FlashbotsExecutor](crate::executors::flashbots_executor::FlashbotsExecutor),
+/// and never broadcasts anything. The most recent outcomes are kept in
+/// memory, and every outcome is logged.
+pub struct SimulationExecutor<M> {
+    client: Arc<M>,
+    outcomes: Arc<Mutex<VecDeque<SimulationOutcome>>>,
+    outcome_capacity: usize,
+    /// Whether the node was found to support neither `trace_call` nor
+    /// `debug_traceCall`, which is only logged once.
+    balance_delta_unsupported: AtomicBool,
+}
+
+/// The outcome of simulating a single transaction.
+#[derive(Debug, Clone)]
+pub struct SimulationOutcome {
+    /// The simulated transaction.
+    pub tx: TypedTransaction,
+
+    /// Whether the transaction executed without reverting.
+    pub success: bool,
+
+    /// Revert reason, if the transaction reverted. Custom errors are kept as
+    /// their hex-encoded revert data.
+    pub revert_reason: Option<String>,
+
+    /// Estimated gas used by the transaction, if it succeeded.
+    pub gas_used: Option<U256>,
+
+    /// Change in the sender's ETH balance, if the node supports `trace_call`
+    /// or `debug_traceCall`.
+    pub balance_delta: Option<I256>,
+}
+
+impl<M> SimulationExecutor<M>
+where
+    M: Middleware,
+    M::Error: 'static,
+{
+    pub fn new(client: Arc<M>) -> Self {
+        Self {
+            client,
+            outcomes: Arc::new(Mutex::new(VecDeque::new())),
+            outcome_capacity: DEFAULT_OUTCOME_CAPACITY,
+            balance_delta_unsupported: AtomicBool::new(false),
+        }
+    }
+
+    /// Sets how many recent outcomes are kept in memory.
+    pub fn with_outcome_capacity(mut self, outcome_capacity: usize) -> Self {
+        self.outcome_capacity = outcome_capacity;
+        self
+    }
+
+    /// Returns the outcomes of the most recent simulations, oldest first.
+    pub fn outcomes(&self) -> Vec<SimulationOutcome> {
+        self.outcomes.lock().unwrap().iter().cloned().collect()
+    }
+
+    /// Simulate a transaction against the pending block and record the outcome.
+    /// Fails if the call fails for another reason than a revert, such as a
+    /// transport error.
+    async fn simulate(&self, tx: TypedTransaction) -> Result<SimulationOutcome> {
+        let block = BlockId::Number(BlockNumber::Pending);
+        let (success, revert_reason) = match self.client.call(&tx, Some(block)).await {
+            Ok(_) => (true, None),
+            Err(e) => match revert_reason(&e) {
+                Some(reason) => (false, Some(reason)),
+                None => return Err(e.into()),
+            },
+        };
+        let gas_used = match success {
+            true => self.client.estimate_gas(&tx, Some(block)).await.ok(),
+            false => None,
+        };
+        let balance_delta = self.balance_delta(&tx).await;
+
+        info!(
+            to = ?tx.to(),
+            success,
+            revert_reason = ?revert_reason,
+            gas_used = ?gas_used,
+            balance_delta = ?balance_delta,
+            "simulated transaction"
+        );
+        let outcome = SimulationOutcome {
+            tx,
+            success,
+            revert_reason,
+            gas_used,
+            balance_delta,
+        };
+        if self.outcome_capacity > 0 {
+            let mut outcomes = self.outcomes.lock().unwrap();
+            if outcomes.len() == self.outcome_capacity {
+                outcomes.pop_front();
+            }
+            outcomes.push_back(outcome.clone());
+        }
+        Ok(outcome)
+    }
+
+    /// Compute the change in the sender's balance, from a `trace_call` state
+    /// diff, or from a `debug_traceCall` prestate diff on nodes without the
+    /// `trace` namespace, such as geth. Returns `None` if the node supports
+    /// neither, or if the transaction has no sender.
+    async fn balance_delta(&self, tx: &TypedTransaction) -> Option<I256> {
+        let Some(sender) = tx.from().copied() else {
+            debug!(to = ?tx.to(), "transaction has no sender, skipping balance delta");
+            return None;
+        };
+        let trace_error = match self.trace_call_balance_delta(tx, sender).await {
+            Ok(delta) => return Some(delta),
+            Err(e) => e,
+        };
+        match self.debug_trace_call_balance_delta(tx, sender).await {
+            Ok(delta) => Some(delta),
+            Err(e) => {
+                if !self.balance_delta_unsupported.swap(true, Ordering::Relaxed) {
+                    warn!(
+                        trace_call_error = %trace_error,
+                        debug_trace_call_error = %e,
+                        "node supports neither trace_call nor debug_traceCall, balance deltas are not simulated"
+                    );
+                }
+                None
+            }
+        }
+    }
+
+    /// Compute the change in the sender's balance from a `trace_call` state diff.
+    async fn trace_call_balance_delta(
+        &self,
+        tx: &TypedTransaction,
+        sender: Address,
+    ) -> Result<I256> {
+        let trace = self
+            .client
+            .trace_call(
+                tx.clone(),
+                vec![TraceType::StateDiff],
+                Some(BlockNumber::Pending),
+            )
+            .await?;
+        let mut state_diff = trace
+            .state_diff
+            .ok_or_else(|| anyhow!("trace_call returned no state diff"))?;
+        let delta = match state_diff.0.remove(&sender).map(|diff| diff.balance) {
+            None | Some(Diff::Same) => I256::zero(),
+            Some(Diff::Born(balance)) => I256::from_raw(balance),
+            Some(Diff::Died(balance)) => -I256::from_raw(balance),
+            Some(Diff::Changed(change)) => I256::from_raw(change.to) - I256::from_raw(change.from),
+        };
+        Ok(delta)
+    }
+
+    /// Compute the change in the sender's balance from a `debug_traceCall`
+    /// prestate diff.
+    async fn debug_trace_call_balance_delta(
+        &self,
+        tx: &TypedTransaction,
+        sender: Address,
+    ) -> Result<I256> {
+        let options = GethDebugTracingCallOptions {
+            tracing_options: GethDebugTracingOptions {
+                tracer: Some(GethDebugTracerType::BuiltInTracer(
+                    GethDebugBuiltInTracerType::PreStateTracer,
+                )),
+                tracer_config: Some(GethDebugTracerConfig::BuiltInTracer(
+                    GethDebugBuiltInTracerConfig::PreStateTracer(PreStateConfig {
+                        diff_mode: Some(true),
+                    }),
+                )),
+                ..GethDebugTracingOptions::default()
+            },
+            ..GethDebugTracingCallOptions::default()
+        };
+        let block = BlockId::Number(BlockNumber::Pending);
+        match self
+            .client
+            .debug_trace_call(tx.clone(), Some(block), options)
+            .await?
+        {
+            GethTrace::Known(GethTraceFrame::PreStateTracer(PreStateFrame::Diff(diff))) => {
+                Ok(prestate_balance_delta(&diff, sender))
+            }
+            trace => Err(anyhow!("unexpected debug_traceCall response: {:?}", trace)),
+        }
+    }
+
+    /// Check the deadline against the latest block.
+    async fn deadline_passed(&self, deadline: &Deadline) -> bool {
+        let current_block = match deadline {
+            Deadline::Block(_) => match self.client.get_block_number().await {
+                Ok(block) => Some(block),
+                Err(e) => {
+                    warn!(
+                        "error getting block number, assuming deadline has not passed: {}",
+                        e
+                    );
+                    None
+                }
+            },
+            Deadline::Instant(_) => None,
+        };
+        deadline.has_passed(current_block)
+    }
+}
+
+/// Compute the change in the balance of `account` from a prestate diff. The
+/// diff only lists modified accounts, and modified fields after the call.
+/// Accounts missing after the call were destroyed.
+fn prestate_balance_delta(diff: &DiffMode, account: Address) -> I256 {
+    let before = diff.pre.get(&account);
+    let after = diff.post.get(&account);
+    let balance_before = before.and_then(|state| state.balance).unwrap_or_default();
+    match (before, after) {
+        (None, None) => I256::zero(),
+        (Some(_), None) => -I256::from_raw(balance_before),
+        (_, Some(state)) => match state.balance {
+            Some(balance) => I256::from_raw(balance) - I256::from_raw(balance_before),
+            None => I256::zero(),
+        },
+    }
+}
+
+/// Extract the revert reason from a failed call, or `None` if the call did
+/// not revert.
+fn revert_reason<E: MiddlewareError>(e: &E) -> Option<String> {
+    let response = e.as_error_response()?;
+    if let Some(data) = response.as_revert_data() {
+        let reason = data
+            .strip_prefix(&REVERT_SELECTOR)
+            .and_then(|payload| String::decode(payload).ok())
+            .unwrap_or_else(|| data.to_string());
+        return Some(reason);
+    }
+    // Reverts without a payload only carry the node's message.
+    response
+        .message
+        .contains("revert")
+        .then(|| response.message.clone())
+}
+
+#[async_trait]
+impl<M> Executor<SubmitTxToMempool> for SimulationExecutor<M>
+where
+    M: Middleware,
+    M::Error: 'static,
+{
+    /// Simulate a transaction instead of sending it to the mempool.
+    async fn execute(&self, action: SubmitTxToMempool) -> Result<Option<Execution>> {
+        let outcome = self.simulate(action.tx).await?;
+        Ok(Some(Execution {
+            gas_used: outcome.gas_used,
+            reverted: !outcome.success,
+            ..Execution::default()
+        }))
+    }
+
+    async fn is_expired(&self, deadline: &Deadline) -> bool {
+        self.deadline_passed(deadline).await
+    }
+}
+
+#[async_trait]
+impl<M> Executor<FlashbotsBundle> for SimulationExecutor<M>
+where
+    M: Middleware,
+    M::Error: 'static,
+{
+    /// Simulate each transaction of a bundle instead of sending it to the relay.
+    /// Transactions are simulated independently, on top of the pending block.
+    async fn execute(&self, action: FlashbotsBundle) -> Result<Option<Execution>> {
+        let mut execution = Execution::default();
+        for tx in action {
+            let outcome = self.simulate(tx).await?;
+            if let Some(gas_used) = outcome.gas_used {
+                execution.gas_used = Some(execution.gas_used.unwrap_or_default() + gas_used);
+            }
+            execution.reverted |= !outcome.success;
+        }
+        Ok(Some(execution))
+    }
+
+    async fn is_expired(&self, deadline: &Deadline) -> bool {
+        self.deadline_passed(deadline).await
+    }
+}
@@ -21,11 +21,16 @@
 
 /// This module contains [collector](types::Collector) implementations.
 pub mod collectors;
+/// This module contains the control plane used to inspect and control a
+/// running [Engine](engine::Engine).
+pub mod control;
 /// This module contains the [Engine](engine::Engine) struct, which is responsible
 /// for orchestrating data flows between components
 pub mod engine;
 /// This module contains [executor](types::Executor) implementations.
 pub mod executors;
+/// This module contains the Prometheus metrics recorded by the [Engine](engine::Engine).
+pub mod metrics;
 /// This module contains the core type definitions for Artemis.
 pub mod types;
 /// This module contains utilities for working with Artemis.
@@ -0,0 +1,272 @@
+use std::convert::Infallible;
+use std::net::SocketAddr;
+
+use anyhow::Result;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{header, Body, Method, Request, Response, Server, StatusCode};
+use prometheus::{
+    Encoder, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGaugeVec, Opts, Registry,
+    TextEncoder,
+};
+use tracing::info;
+
+use crate::engine::Health;
+
+/// Prometheus metrics recorded by the [Engine](crate::engine::Engine) for
+/// every collector, strategy and executor. Metrics are cheap to clone and
+/// can be served over HTTP with [serve](EngineMetrics::serve).
+#[derive(Clone)]
+pub struct EngineMetrics {
+    registry: Registry,
+    events_received: IntCounterVec,
+    collector_restarts: IntCounterVec,
+    events_processed: IntCounterVec,
+    process_event_seconds: HistogramVec,
+    actions_emitted: IntCounterVec,
+    strategy_resyncs: IntCounterVec,
+    executions: IntCounterVec,
+    execution_seconds: HistogramVec,
+    broadcast_lag: IntCounterVec,
+    health: IntGaugeVec,
+}
+
+/// Outcome of an action handed to an executor.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ExecutionOutcome {
+    Success,
+    Failure,
+    Ignored,
+    Expired,
+}
+
+impl ExecutionOutcome {
+    fn as_str(&self) -> &'static str {
+        match self {
+            ExecutionOutcome::Success => "success",
+            ExecutionOutcome::Failure => "failure",
+            ExecutionOutcome::Ignored => "ignored",
+            ExecutionOutcome::Expired => "expired",
+        }
+    }
+}
+
+impl EngineMetrics {
+    /// Creates the engine metrics, registered in a new registry.
+    pub fn new() -> Result<Self> {
+        Self::with_registry(Registry::new())
+    }
+
+    /// Creates the engine metrics, registered in the given registry, so that
+    /// they can be served alongside custom metrics.
+    pub fn with_registry(registry: Registry) -> Result<Self> {
+        let events_received = IntCounterVec::new(
+            Opts::new(
+                "arbot_events_received_total",
+                "Events received from each collector.",
+            ),
+            &["collector"],
+        )?;
+        let collector_restarts = IntCounterVec::new(
+            Opts::new(
+                "arbot_collector_restarts_total",
+                "Restarts of each collector.",
+            ),
+            &["collector"],
+        )?;
+        let events_processed = IntCounterVec::new(
+            Opts::new(
+                "arbot_events_processed_total",
+                "Events processed by each strategy.",
+            ),
+            &["strategy"],
+        )?;
+        let process_event_seconds = HistogramVec::new(
+            HistogramOpts::new(
+                "arbot_process_event_seconds",
+                "Time each strategy spends processing an event.",
+            ),
+            &["strategy"],
+        )?;
+        let actions_emitted = IntCounterVec::new(
+            Opts::new(
+                "arbot_actions_emitted_total",
+                "Actions emitted by each strategy.",
+            ),
+            &["strategy"],
+        )?;
+        let strategy_resyncs = IntCounterVec::new(
+            Opts::new("arbot_strategy_resyncs_total", "Resyncs of each strategy."),
+            &["strategy"],
+        )?;
+        let executions = IntCounterVec::new(
+            Opts::new(
+                "arbot_executions_total",
+                "Actions handled by each executor, by outcome.",
+            ),
+            &["executor", "outcome"],
+        )?;
+        let execution_seconds = HistogramVec::new(
+            HistogramOpts::new(
+                "arbot_execution_seconds",
+                "Time each executor spends executing an action.",
+            ),
+            &["executor"],
+        )?;
+        let broadcast_lag = IntCounterVec::new(
+            Opts::new(
+                "arbot_broadcast_lag_total",
+                "Messages each strategy or executor missed because it fell behind its channel.",
+            ),
+            &["component"],
+        )?;
+        let health = IntGaugeVec::new(
+            Opts::new(
+                "arbot_component_healthy",
+                "Whether each component is healthy (1), unhealthy (0) or stopped (-1).",
+            ),
+            &["component"],
+        )?;
+
+        registry.register(Box::new(events_received.clone()))?;
+        registry.register(Box::new(collector_restarts.clone()))?;
+        registry.register(Box::new(events_processed.clone()))?;
+        registry.register(Box::new(process_event_seconds.clone()))?;
+        registry.register(Box::new(actions_emitted.clone()))?;
+        registry.register(Box::new(strategy_resyncs.clone()))?;
+        registry.register(Box::new(executions.clone()))?;
+        registry.register(Box::new(execution_seconds.clone()))?;
+        registry.register(Box::new(broadcast_lag.clone()))?;
+        registry.register(Box::new(health.clone()))?;
+
+        Ok(Self {
+            registry,
+            events_received,
+            collector_restarts,
+            events_processed,
+            process_event_seconds,
+            actions_emitted,
+            strategy_resyncs,
+            executions,
+            execution_seconds,
+            broadcast_lag,
+            health,
+        })
+    }
+
+    /// Returns the registry the metrics are registered in.
+    pub fn registry(&self) -> &Registry {
+        &self.registry
+    }
+
+    /// Encodes every metric in the registry in the Prometheus text format.
+    pub fn encode(&self) -> Result<String> {
+        let mut buffer = vec![];
+        TextEncoder::new().encode(&self.registry.gather(), &mut buffer)?;
+        Ok(String::from_utf8(buffer)?)
+    }
+
+    /// Serves the metrics on `GET /metrics` at the given address until the
+    /// server fails.
+    pub async fn serve(self, addr: SocketAddr) -> Result<()> {
+        let make_service = make_service_fn(move |_| {
+            let metrics = self.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |request| {
+                    let response = metrics.respond(request);
+                    async move { Ok::<_, Infallible>(response) }
+                }))
+            }
+        });
+        let server = Server::try_bind(&addr)?.serve(make_service);
+        info!("serving metrics on http://{}/metrics", server.local_addr());
+        server.await?;
+        Ok(())
+    }
+
+    fn respond(&self, request: Request<Body>) -> Response<Body> {
+        if request.method() != Method::GET || request.uri().path() != "/metrics" {
+            return Response::builder()
+                .status(StatusCode::NOT_FOUND)
+                .body(Body::empty())
+                .unwrap();
+        }
+        match self.encode() {
+            Ok(body) => Response::builder()
+                .header(header::CONTENT_TYPE, TextEncoder::new().format_type())
+                .body(Body::from(body))
+                .unwrap(),
+            Err(e) => Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body(Body::from(e.to_string()))
+                .unwrap(),
+        }
+    }
+
+    pub(crate) fn event_received(&self, collector: &str) {
+        self.events_received.with_label_values(&[collector]).inc();
+    }
+
+    pub(crate) fn collector_restarted(&self, collector: &str) {
+        self.collector_restarts
+            .with_label_values(&[collector])
+            .inc();
+    }
+
+    pub(crate) fn event_processed(&self, strategy: &str, seconds: f64) {
+        self.events_processed.with_label_values(&[strategy]).inc();
+        self.process_event_seconds
+            .with_label_values(&[strategy])
+            .observe(seconds);
+    }
+
+    pub(crate) fn actions_emitted_counter(&self, strategy: &str) -> IntCounter {
+        self.actions_emitted.with_label_values(&[strategy])
+    }
+
+    pub(crate) fn actions_emitted(&self, strategy: &str, count: usize) {
+        self.actions_emitted
+            .with_label_values(&[strategy])
+            .inc_by(count as u64);
+    }
+
+    pub(crate) fn strategy_resynced(&self, strategy: &str) {
+        self.strategy_resyncs.with_label_values(&[strategy]).inc();
+    }
+
+    pub(crate) fn action_executed(
+        &self,
+        executor: &str,
+        outcome: ExecutionOutcome,
+        seconds: Option<f64>,
+    ) {
+        self.executions
+            .with_label_values(&[executor, outcome.as_str()])
+            .inc();
+        if let Some(seconds) = seconds {
+            self.execution_seconds
+                .with_label_values(&[executor])
+                .observe(seconds);
+        }
+    }
+
+    pub(crate) fn lagged(&self, component: &str, skipped: u64) {
+        self.broadcast_lag
+            .with_label_values(&[component])
+            .inc_by(skipped);
+    }
+
+    pub(crate) fn set_health(&self, component: &str, health: &Health) {
+        let value = match health {
+            Health::Healthy => 1,
+            Health::Unhealthy(_) => 0,
+            Health::Stopped => -1,
+        };
+        self.health.with_label_values(&[component]).set(value);
+    }
+}
+
+impl Default for EngineMetrics {
+    fn default() -> Self {
+        Self::new().expect("engine metrics are valid")
+    }
+}
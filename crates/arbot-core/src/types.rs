@@ -1,11 +1,21 @@
 use anyhow::Result;
 use async_trait::async_trait;
-use ethers::types::Transaction;
+use ethers::types::{Transaction, H256, U256, U64};
+use prometheus::IntCounter;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::future::Future;
 use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Instant, SystemTime};
+use tokio::sync::broadcast::Sender;
+use tokio::sync::oneshot;
 use tokio_stream::Stream;
 use tokio_stream::StreamExt;
 
-use crate::collectors::block_collector::NewBlock;
+use crate::collectors::block_collector::{NewBlock, Reorg};
+use crate::collectors::interval_collector::Tick;
 use crate::collectors::opensea_order_collector::OpenseaOrder;
 use crate::executors::flashbots_executor::FlashbotsBundle;
 use crate::executors::mempool_executor::SubmitTxToMempool;
@@ -13,11 +23,38 @@ use crate::executors::mempool_executor::SubmitTxToMempool;
 /// A stream of events emitted by a [Collector](Collector).
 pub type CollectorStream<'a, E> = Pin<Box<dyn Stream<Item = E> + Send + 'a>>;
 
+/// An event as it travels through the engine, along with the name of the
+/// collector that produced it and the time the engine received it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EventEnvelope<E> {
+    /// Identifier the engine assigned to the event.
+    #[serde(default)]
+    pub id: EventId,
+    /// Name of the collector that produced the event.
+    pub source: Arc<str>,
+    /// Time at which the engine received the event.
+    pub received_at: SystemTime,
+    /// Whether the event is an execution report published by the engine,
+    /// rather than an event produced by a collector.
+    #[serde(default)]
+    pub execution_report: bool,
+    /// The event itself.
+    pub event: E,
+}
+
+/// Predicate selecting the events a [Strategy](Strategy) consumes.
+pub type EventFilter<E> = Arc<dyn Fn(&E) -> bool + Send + Sync>;
+
 /// Collector trait, which defines a source of events.
 #[async_trait]
 pub trait Collector<E>: Send + Sync {
     /// Returns the core event stream for the collector.
     async fn get_event_stream(&self) -> Result<CollectorStream<E>>;
+
+    /// Name used to identify the collector in logs.
+    fn name(&self) -> &str {
+        short_type_name::<Self>()
+    }
 }
 
 /// Strategy trait, which defines the core logic for each opportunity.
@@ -27,15 +64,333 @@ pub trait Strategy<E, A>: Send + Sync {
     /// onchain data.
     async fn sync_state(&mut self) -> Result<()>;
 
-    /// Process an event, and return an action if needed.
-    async fn process_event(&mut self, event: E) -> Option<A>;
+    /// Process an event, and return any actions it triggers. Returning an
+    /// error marks the strategy unhealthy: the engine stops feeding it events
+    /// until [sync_state](Strategy::sync_state) succeeds again.
+    async fn process_event(&mut self, event: E) -> Result<Vec<A>>;
+
+    /// Returns a filter selecting the events the strategy consumes, for
+    /// example the event variants it handles. The engine only routes matching
+    /// events to the strategy, so that it neither clones nor wakes up for
+    /// events it ignores. Called once, before the engine starts. Returns
+    /// `None` to receive every event.
+    fn event_filter(&self) -> Option<EventFilter<E>> {
+        None
+    }
+
+    /// Returns a filter selecting the events which update the strategy's
+    /// state, for example new blocks which opportunity events are priced
+    /// against. The engine delivers these on a priority lane, ahead of any
+    /// other queued events. Called once, before the engine starts. Returns
+    /// `None` to process events in the order they were received.
+    fn priority_events(&self) -> Option<EventFilter<E>> {
+        None
+    }
+
+    /// Hands the strategy an [ActionSink](ActionSink), which it can keep to
+    /// submit actions outside of [process_event](Strategy::process_event), for
+    /// example from background tasks it spawns. Called by the engine before
+    /// [sync_state](Strategy::sync_state).
+    fn set_action_sink(&mut self, _sink: ActionSink<A>) {}
+
+    /// Returns the deadline after which `action` is no longer worth
+    /// executing, if any. Called by the engine for every action returned from
+    /// [process_event](Strategy::process_event).
+    fn action_deadline(&self, _action: &A) -> Option<Deadline> {
+        None
+    }
+
+    /// Returns a snapshot of the strategy's state, which the engine persists
+    /// when snapshots are enabled with
+    /// [with_snapshots](crate::engine::Engine::with_snapshots). Returns `None`
+    /// if the strategy does not support snapshots.
+    fn snapshot(&self) -> Result<Option<serde_json::Value>> {
+        Ok(None)
+    }
+
+    /// Restores a snapshot previously returned by [snapshot](Strategy::snapshot).
+    /// Called by the engine before [sync_state](Strategy::sync_state), which
+    /// then only needs to catch up from the snapshot.
+    fn restore(&mut self, _snapshot: serde_json::Value) -> Result<()> {
+        Ok(())
+    }
+
+    /// Returns a summary of the strategy's internal state, for example the
+    /// number of tracked pools, served by the engine's control plane.
+    fn state_summary(&self) -> serde_json::Value {
+        serde_json::Value::Null
+    }
+
+    /// Called when the strategy fell behind the event channel and `skipped`
+    /// events were dropped. The returned [LagPolicy](LagPolicy) tells the
+    /// engine how to recover before processing further events.
+    fn on_lag(&mut self, _skipped: u64) -> LagPolicy {
+        LagPolicy::Skip
+    }
+
+    /// Release any resources held by the strategy. Called by the engine during
+    /// shutdown, once all queued events have been processed.
+    async fn teardown(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Name used to identify the strategy in logs.
+    fn name(&self) -> &str {
+        short_type_name::<Self>()
+    }
+}
+
+/// How a [Strategy](Strategy) recovers after missing events.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LagPolicy {
+    /// Ignore the missed events and keep processing.
+    Skip,
+    /// Call [sync_state](Strategy::sync_state) again before processing further events.
+    Resync,
+}
+
+/// Unique identifier the engine assigns to every event.
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize,
+)]
+pub struct EventId(pub u64);
+
+impl EventId {
+    /// Returns a new identifier, unique within the process.
+    pub(crate) fn next() -> Self {
+        static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+        Self(NEXT_ID.fetch_add(1, Ordering::Relaxed))
+    }
+}
+
+impl fmt::Display for EventId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Unique identifier the engine assigns to every action.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct ActionId(pub u64);
+
+impl ActionId {
+    /// Returns a new identifier, unique within the process.
+    pub(crate) fn next() -> Self {
+        static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+        Self(NEXT_ID.fetch_add(1, Ordering::Relaxed))
+    }
+}
+
+impl fmt::Display for ActionId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Point after which an action is no longer worth executing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Deadline {
+    /// The action must be included in this block or earlier, so it expires
+    /// once this block has been mined.
+    Block(U64),
+    /// The action expires at this instant.
+    Instant(Instant),
+}
+
+impl Deadline {
+    /// Returns whether the deadline has passed, given the latest block number
+    /// if it is known. Block deadlines never pass when the block is unknown.
+    pub fn has_passed(&self, current_block: Option<U64>) -> bool {
+        match self {
+            Deadline::Block(block) => current_block.is_some_and(|current| current >= *block),
+            Deadline::Instant(instant) => Instant::now() >= *instant,
+        }
+    }
+}
+
+/// An action as it travels from a strategy to the executors, along with where
+/// it came from and how long it stays valid.
+#[derive(Debug, Clone)]
+pub struct ActionEnvelope<A> {
+    /// Identifier the engine assigned to the action.
+    pub id: ActionId,
+    /// Name of the strategy that produced the action.
+    pub strategy: Arc<str>,
+    /// Identifier of the event that triggered the action, if any.
+    pub event_id: Option<EventId>,
+    /// Time at which the engine received the triggering event, if any.
+    pub event_received_at: Option<SystemTime>,
+    /// Deadline after which executors drop the action.
+    pub deadline: Option<Deadline>,
+    /// The action itself.
+    pub action: A,
+}
+
+/// A broadcast sender shared between tasks, which can be closed by taking it.
+pub(crate) type SharedSender<T> = Arc<RwLock<Option<Sender<T>>>>;
+
+/// A handle through which a [Strategy](Strategy) can submit actions at any
+/// time. The sink is closed once the strategy has been torn down.
+pub struct ActionSink<A> {
+    strategy: Arc<str>,
+    sender: SharedSender<ActionEnvelope<A>>,
+    emitted: IntCounter,
+}
+
+impl<A> ActionSink<A> {
+    pub(crate) fn new(
+        strategy: Arc<str>,
+        sender: Sender<ActionEnvelope<A>>,
+        emitted: IntCounter,
+    ) -> Self {
+        Self {
+            strategy,
+            sender: Arc::new(RwLock::new(Some(sender))),
+            emitted,
+        }
+    }
+
+    /// Submits an action to the executors, returning the identifier it was
+    /// assigned.
+    pub fn send(&self, action: A) -> Result<ActionId> {
+        self.send_envelope(action, None)
+    }
+
+    /// Submits an action which executors drop once `deadline` has passed,
+    /// returning the identifier it was assigned.
+    pub fn send_with_deadline(&self, action: A, deadline: Deadline) -> Result<ActionId> {
+        self.send_envelope(action, Some(deadline))
+    }
+
+    fn send_envelope(&self, action: A, deadline: Option<Deadline>) -> Result<ActionId> {
+        match self.sender.read().unwrap().as_ref() {
+            Some(sender) => {
+                let id = ActionId::next();
+                let envelope = ActionEnvelope {
+                    id,
+                    strategy: self.strategy.clone(),
+                    event_id: None,
+                    event_received_at: None,
+                    deadline,
+                    action,
+                };
+                sender
+                    .send(envelope)
+                    .map_err(|_| anyhow::anyhow!("no executor is receiving actions"))?;
+                self.emitted.inc();
+                Ok(id)
+            }
+            None => Err(anyhow::anyhow!("action sink is closed")),
+        }
+    }
+
+    /// Closes the sink, so that the engine can shut down the action channel.
+    pub(crate) fn close(&self) {
+        self.sender.write().unwrap().take();
+    }
+}
+
+impl<A> Clone for ActionSink<A> {
+    fn clone(&self) -> Self {
+        Self {
+            strategy: self.strategy.clone(),
+            sender: self.sender.clone(),
+            emitted: self.emitted.clone(),
+        }
+    }
 }
 
 /// Executor trait, responsible for executing actions returned by strategies.
 #[async_trait]
 pub trait Executor<A>: Send + Sync {
-    /// Execute an action.
-    async fn execute(&self, action: A) -> Result<()>;
+    /// Execute an action. Returns details of the execution, or `None` if the
+    /// executor ignored the action.
+    async fn execute(&self, action: A) -> Result<Option<Execution>>;
+
+    /// Returns whether an action with this deadline has expired and should be
+    /// dropped instead of executed. Executors that know the chain head should
+    /// override this, since the default only checks instant deadlines.
+    async fn is_expired(&self, deadline: &Deadline) -> bool {
+        deadline.has_passed(None)
+    }
+
+    /// Returns a key for actions which must not run concurrently, for example
+    /// the sender of a nonce-sensitive transaction. When the executor handles
+    /// several actions at once, actions with the same key still run one after
+    /// another, in the order they were received. An action may let the next
+    /// one start early with [release_ordering](release_ordering).
+    fn ordering_key(&self, _action: &A) -> Option<String> {
+        None
+    }
+
+    /// Release any resources held by the executor. Called by the engine during
+    /// shutdown, once in-flight actions have completed or timed out.
+    async fn teardown(&self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Name used to identify the executor in logs.
+    fn name(&self) -> &str {
+        short_type_name::<Self>()
+    }
+}
+
+tokio::task_local! {
+    /// Lets the next action with the same ordering key start, while the
+    /// action being executed holds it.
+    static ORDERING_RELEASE: Mutex<Option<oneshot::Sender<()>>>;
+}
+
+/// Lets the next action with the same [ordering_key](Executor::ordering_key)
+/// start before the action being executed completes. Executors call it once
+/// the part of an action that must run in order is done, for example once a
+/// transaction is sent and before waiting for its receipt. Does nothing
+/// outside of an ordered action.
+pub fn release_ordering() {
+    let _ = ORDERING_RELEASE.try_with(|release| release.lock().unwrap().take());
+}
+
+/// Runs an action holding an ordering key, which is released by dropping
+/// `done` once the action calls [release_ordering](release_ordering) or
+/// completes.
+pub(crate) async fn hold_ordering<F: Future>(
+    done: Option<oneshot::Sender<()>>,
+    action: F,
+) -> F::Output {
+    ORDERING_RELEASE.scope(Mutex::new(done), action).await
+}
+
+/// Details of an action carried out by an [Executor](Executor).
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Execution {
+    /// Hash of the submitted transaction, if any.
+    pub tx_hash: Option<H256>,
+    /// Block in which the transaction was included, if known.
+    pub block_number: Option<U64>,
+    /// Gas used by the transaction, if known.
+    pub gas_used: Option<U256>,
+    /// Whether the transaction reverted.
+    pub reverted: bool,
+    /// Hash of the submitted bundle, if the action was sent as a bundle.
+    #[serde(default)]
+    pub bundle_hash: Option<H256>,
+    /// Hashes of the bundle's transactions, in bundle order.
+    #[serde(default)]
+    pub bundle_tx_hashes: Vec<H256>,
+}
+
+/// Report of an executed action, which the engine can publish back to
+/// strategies as an event. See [report_executions](crate::engine::Engine::report_executions).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExecutionReport<A> {
+    /// Identifier of the executed action.
+    pub action_id: ActionId,
+    /// Name of the executor which handled the action.
+    pub executor: String,
+    /// The executed action.
+    pub action: A,
+    /// Details of the execution, or the error message if it failed.
+    pub result: Result<Execution, String>,
 }
 
 /// CollectorMap is a wrapper around a [Collector](Collector) that maps outgoing
@@ -43,10 +398,22 @@ pub trait Executor<A>: Send + Sync {
 pub struct CollectorMap<E, F> {
     collector: Box<dyn Collector<E>>,
     f: F,
+    name: Option<String>,
 }
 impl<E, F> CollectorMap<E, F> {
     pub fn new(collector: Box<dyn Collector<E>>, f: F) -> Self {
-        Self { collector, f }
+        Self {
+            collector,
+            f,
+            name: None,
+        }
+    }
+
+    /// Names the collector, which otherwise takes the name of the wrapped
+    /// collector. Distinguishes several collectors of the same type.
+    pub fn with_name(mut self, name: impl Into<String>) -> Self {
+        self.name = Some(name.into());
+        self
     }
 }
 
@@ -63,6 +430,11 @@ where
         let stream = stream.map(f);
         Ok(Box::pin(stream))
     }
+    fn name(&self) -> &str {
+        self.name
+            .as_deref()
+            .unwrap_or_else(|| self.collector.name())
+    }
 }
 
 /// ExecutorMap is a wrapper around an [Executor](Executor) that maps incoming
@@ -70,35 +442,73 @@ where
 pub struct ExecutorMap<A, F> {
     executor: Box<dyn Executor<A>>,
     f: F,
+    name: Option<String>,
 }
 
 impl<A, F> ExecutorMap<A, F> {
     pub fn new(executor: Box<dyn Executor<A>>, f: F) -> Self {
-        Self { executor, f }
+        Self {
+            executor,
+            f,
+            name: None,
+        }
+    }
+
+    /// Names the executor, which otherwise takes the name of the wrapped
+    /// executor. Distinguishes several executors of the same type.
+    pub fn with_name(mut self, name: impl Into<String>) -> Self {
+        self.name = Some(name.into());
+        self
     }
 }
 
 #[async_trait]
 impl<A1, A2, F> Executor<A1> for ExecutorMap<A2, F>
 where
-    A1: Send + Sync + 'static,
+    A1: Clone + Send + Sync + 'static,
     A2: Send + Sync + 'static,
     F: Fn(A1) -> Option<A2> + Send + Sync + Clone + 'static,
 {
-    async fn execute(&self, action: A1) -> Result<()> {
+    async fn execute(&self, action: A1) -> Result<Option<Execution>> {
         let action = (self.f)(action);
         match action {
             Some(action) => self.executor.execute(action).await,
-            None => Ok(()),
+            None => Ok(None),
         }
     }
+
+    async fn is_expired(&self, deadline: &Deadline) -> bool {
+        self.executor.is_expired(deadline).await
+    }
+
+    fn ordering_key(&self, action: &A1) -> Option<String> {
+        let action = (self.f)(action.clone())?;
+        self.executor.ordering_key(&action)
+    }
+
+    async fn teardown(&self) -> Result<()> {
+        self.executor.teardown().await
+    }
+
+    fn name(&self) -> &str {
+        self.name.as_deref().unwrap_or_else(|| self.executor.name())
+    }
+}
+
+/// Returns the name of a type without its module path or generic parameters.
+pub(crate) fn short_type_name<T: ?Sized>() -> &'static str {
+    let name = std::any::type_name::<T>();
+    let name = name.split('<').next().unwrap_or(name);
+    name.rsplit("::").next().unwrap_or(name)
 }
 
 /// Convenience enum containing all the events that can be emitted by collectors.
 pub enum Events {
     NewBlock(NewBlock),
+    Reorg(Reorg),
     Transaction(Transaction),
     OpenseaOrder(Box<OpenseaOrder>),
+    Tick(Tick),
 }
 
 /// Convenience enum containing all the actions that can be executed by executors.
@@ -0,0 +1,69 @@
+use std::path::PathBuf;
+
+use anyhow::Result;
+use serde::Serialize;
+use tokio::{
+    fs::OpenOptions,
+    io::AsyncWriteExt,
+    sync::broadcast::{error::RecvError, Receiver},
+};
+use tracing::{error, info, warn};
+
+use crate::types::EventEnvelope;
+
+/// Records engine events to an append-only [JSON Lines](https://jsonlines.org)
+/// file. Each line holds one [EventEnvelope](EventEnvelope): the event, the
+/// collector that produced it, and the time the engine received it. Events
+/// that fail to serialize or write are logged and left out of the recording.
+#[derive(Debug, Clone)]
+pub struct EventRecorder {
+    path: PathBuf,
+}
+
+impl EventRecorder {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+
+    /// Appends every event received on the channel to the file, until the
+    /// channel closes.
+    pub(crate) async fn run<E: Serialize + Clone>(
+        self,
+        mut receiver: Receiver<EventEnvelope<E>>,
+    ) -> Result<()> {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .await?;
+        info!("recording events to {}", self.path.display());
+
+        loop {
+            match receiver.recv().await {
+                Ok(envelope) => {
+                    let mut line = match serde_json::to_vec(&envelope) {
+                        Ok(line) => line,
+                        Err(e) => {
+                            error!(event_id = %envelope.id, "error serializing recorded event: {}", e);
+                            continue;
+                        }
+                    };
+                    line.push(b'\n');
+                    if let Err(e) = file.write_all(&line).await {
+                        error!(event_id = %envelope.id, "error recording event: {}", e);
+                    }
+                }
+                Err(RecvError::Closed) => break,
+                Err(RecvError::Lagged(skipped)) => {
+                    warn!(
+                        skipped,
+                        "event recorder lagged, events are missing from the recording"
+                    );
+                }
+            }
+        }
+
+        file.flush().await?;
+        Ok(())
+    }
+}
@@ -1,5 +1,14 @@
 //! Utilities for working with Artemis.
 
+/// This module implements recording of engine events to disk.
+pub mod event_recorder;
+
+/// This module implements a minimal Phoenix channels websocket client.
+pub mod phoenix_socket;
+
+/// This module implements persistence of strategy snapshots.
+pub mod snapshot_store;
+
 /// This module implements state overriding middleware.
 /// 此模块实现了状态覆盖中间件
 pub mod state_override_middleware;
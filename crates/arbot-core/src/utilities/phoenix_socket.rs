@@ -0,0 +1,104 @@
+use std::collections::VecDeque;
+
+use anyhow::{bail, Result};
+use futures::{SinkExt, StreamExt};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tokio::net::TcpStream;
+use tokio_tungstenite::{connect_async, tungstenite::Message, MaybeTlsStream, WebSocketStream};
+
+/// A message of the Phoenix channels protocol, in its JSON object format.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PhoenixMessage {
+    pub topic: String,
+    pub event: String,
+    pub payload: Value,
+    /// Reference used to match replies to the messages that caused them.
+    #[serde(rename = "ref")]
+    pub reference: Option<String>,
+}
+
+/// A minimal client for a Phoenix channels websocket, such as the OpenSea
+/// stream. The socket does not send heartbeats by itself, so that its owner
+/// can decide when the connection is dead.
+pub struct PhoenixSocket {
+    stream: WebSocketStream<MaybeTlsStream<TcpStream>>,
+    next_ref: u64,
+    /// Messages received while joining a channel, not yet returned by
+    /// [next_message](PhoenixSocket::next_message).
+    buffered: VecDeque<PhoenixMessage>,
+}
+
+impl PhoenixSocket {
+    /// Connects to the websocket at `url`.
+    pub async fn connect(url: &str) -> Result<Self> {
+        let (stream, _) = connect_async(url).await?;
+        Ok(Self {
+            stream,
+            next_ref: 0,
+            buffered: VecDeque::new(),
+        })
+    }
+
+    /// Sends a message, returning its reference.
+    pub async fn send(&mut self, topic: &str, event: &str, payload: Value) -> Result<String> {
+        self.next_ref += 1;
+        let reference = self.next_ref.to_string();
+        let message = PhoenixMessage {
+            topic: topic.to_string(),
+            event: event.to_string(),
+            payload,
+            reference: Some(reference.clone()),
+        };
+        self.stream
+            .send(Message::Text(serde_json::to_string(&message)?))
+            .await?;
+        Ok(reference)
+    }
+
+    /// Joins a channel, waiting for the server to accept the join. Messages
+    /// received in the meantime, such as events of channels joined earlier,
+    /// are kept for [next_message](PhoenixSocket::next_message).
+    pub async fn join(&mut self, topic: &str) -> Result<()> {
+        let reference = self.send(topic, "phx_join", json!({})).await?;
+        while let Some(message) = self.receive().await? {
+            if message.event == "phx_reply"
+                && message.reference.as_deref() == Some(reference.as_str())
+            {
+                if message.payload["status"] == "ok" {
+                    return Ok(());
+                }
+                bail!("joining {} failed: {}", topic, message.payload);
+            }
+            self.buffered.push_back(message);
+        }
+        bail!("socket closed while joining {}", topic)
+    }
+
+    /// Sends a heartbeat, returning its reference so that the caller can
+    /// match the server's reply.
+    pub async fn heartbeat(&mut self) -> Result<String> {
+        self.send("phoenix", "heartbeat", json!({})).await
+    }
+
+    /// Receives the next message, or `None` once the socket is closed.
+    pub async fn next_message(&mut self) -> Result<Option<PhoenixMessage>> {
+        if let Some(message) = self.buffered.pop_front() {
+            return Ok(Some(message));
+        }
+        self.receive().await
+    }
+
+    /// Receives the next message from the websocket.
+    async fn receive(&mut self) -> Result<Option<PhoenixMessage>> {
+        while let Some(message) = self.stream.next().await {
+            match message? {
+                Message::Text(text) => return Ok(Some(serde_json::from_str(&text)?)),
+                Message::Close(_) => return Ok(None),
+                // pings are answered by the websocket itself
+                _ => {}
+            }
+        }
+        Ok(None)
+    }
+}
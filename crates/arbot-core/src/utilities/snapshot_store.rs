@@ -0,0 +1,89 @@
+use std::path::PathBuf;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use serde_json::Value;
+use tokio::fs;
+
+/// Default amount of time between two snapshots of a strategy.
+const DEFAULT_SNAPSHOT_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Persists [Strategy](crate::types::Strategy) snapshots to a directory, one
+/// JSON file per strategy, named after the strategy.
+#[derive(Debug, Clone)]
+pub struct SnapshotStore {
+    dir: PathBuf,
+    interval: Duration,
+}
+
+impl SnapshotStore {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            dir: dir.into(),
+            interval: DEFAULT_SNAPSHOT_INTERVAL,
+        }
+    }
+
+    /// Sets how often the engine snapshots each strategy while running.
+    /// Strategies are also snapshotted once they stop.
+    pub fn with_interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// Returns a store for collector checkpoints, kept in a `checkpoints`
+    /// subdirectory so that they never overwrite a strategy snapshot of the
+    /// same name.
+    pub fn checkpoints(&self) -> SnapshotStore {
+        Self {
+            dir: self.dir.join("checkpoints"),
+            interval: self.interval,
+        }
+    }
+
+    pub(crate) fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    /// Loads the latest snapshot of a strategy, if one was saved.
+    pub async fn load(&self, name: &str) -> Result<Option<Value>> {
+        let path = self.path(name);
+        let contents = match fs::read(&path).await {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => {
+                return Err(e).with_context(|| format!("reading snapshot {}", path.display()))
+            }
+        };
+        let snapshot = serde_json::from_slice(&contents)
+            .with_context(|| format!("parsing snapshot {}", path.display()))?;
+        Ok(Some(snapshot))
+    }
+
+    /// Saves the snapshot of a strategy, replacing the previous one. The file
+    /// is written next to its destination first, so that a crash never leaves
+    /// a partial snapshot behind.
+    pub async fn save(&self, name: &str, snapshot: &Value) -> Result<()> {
+        fs::create_dir_all(&self.dir).await?;
+        let path = self.path(name);
+        let partial = path.with_extension("json.partial");
+        fs::write(&partial, serde_json::to_vec(snapshot)?).await?;
+        fs::rename(&partial, &path).await?;
+        Ok(())
+    }
+
+    /// Saves a snapshot like [save](SnapshotStore::save), blocking the
+    /// current thread. Used where awaiting is not possible, such as in `drop`.
+    pub(crate) fn save_blocking(&self, name: &str, snapshot: &Value) -> Result<()> {
+        std::fs::create_dir_all(&self.dir)?;
+        let path = self.path(name);
+        let partial = path.with_extension("json.partial");
+        std::fs::write(&partial, serde_json::to_vec(snapshot)?)?;
+        std::fs::rename(&partial, &path)?;
+        Ok(())
+    }
+
+    fn path(&self, name: &str) -> PathBuf {
+        self.dir.join(format!("{}.json", name))
+    }
+}
@@ -1,17 +1,59 @@
-use std::{sync::Arc, time::Duration};
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
 
+use anyhow::Result;
 use arbot_core::{
-    collectors::{block_collector::BlockCollector, mempool_collector::MempoolCollector},
-    executors::mempool_executor::{MempoolExecutor, SubmitTxToMempool},
-    types::{Collector, Executor},
+    collectors::{
+        block_collector::{BlockCheckpoint, BlockCollector, BlockEvent, NewBlock},
+        interval_collector::{IntervalCollector, Tick},
+        log_collector::{LogAddresses, LogCollector},
+        mempool_collector::{DecodedPendingTx, MempoolCollector, MempoolFilter},
+        opensea_order_collector::{OpenseaEvent, OpenseaEventKind, OpenseaOrderCollector},
+        racing_collector::RacingCollector,
+        replay_collector::{ReplayCollector, ReplaySpeed},
+        seaport_event_collector::{
+            CounterIncrementedFilter, OrderCancelledFilter, OrderFulfilledFilter,
+            OrderValidated1Filter, OrderValidated2Filter, SeaportEventCollector, SeaportEvents,
+        },
+    },
+    engine::{Engine, Health, RestartPolicy, ShutdownHandle},
+    executors::{
+        mempool_executor::{MempoolExecutor, SubmitTxToMempool},
+        simulation_executor::SimulationExecutor,
+    },
+    types::{
+        release_ordering, ActionSink, Collector, CollectorMap, CollectorStream, Deadline,
+        EventFilter, Execution, Executor, ExecutorMap, LagPolicy, Strategy,
+    },
+    utilities::{
+        event_recorder::EventRecorder, phoenix_socket::PhoenixSocket, snapshot_store::SnapshotStore,
+    },
 };
+use async_trait::async_trait;
 use ethers::providers::StreamExt;
 use ethers::{
-    providers::{Middleware, Provider, Ws},
-    types::{BlockNumber, TransactionRequest, U256},
-    utils::{Anvil, AnvilInstance},
+    abi::{encode, AbiEncode, RawLog, Tokenizable},
+    contract::{EthCall, EthEvent, EthLogDecode},
+    providers::{Http, JsonRpcError, Middleware, MockResponse, Provider, Ws},
+    types::{
+        Address, BlockNumber, Bytes, Filter, Transaction, TransactionRequest, H256, I256, U256, U64,
+    },
+    utils::{keccak256, Anvil, AnvilInstance},
+};
+use futures::SinkExt;
+use opensea_stream::Network;
+use serde_json::{json, Value};
+use tokio::{
+    net::{TcpListener, TcpStream},
+    task::JoinSet,
+    time::{sleep, timeout},
 };
-use tokio::time::sleep;
+use tokio_tungstenite::tungstenite::Message;
 
 /// Spawns Anvil and instantiates an Http provider.
 pub async fn spawn_anvil() -> (Provider<Ws>, AnvilInstance) {
@@ -30,13 +72,187 @@ async fn test_block_collector_sends_blocks() {
     let provider = Arc::new(provider);
     let block_collector = BlockCollector::new(provider.clone());
     let block_stream = block_collector.get_event_stream().await.unwrap();
-    let block_a = block_stream.into_future().await.0.unwrap();
+    let block_a = match block_stream.into_future().await.0.unwrap() {
+        BlockEvent::NewBlock(block) => block,
+        event => panic!("unexpected event {:?}", event),
+    };
     let block_b = provider
         .get_block(BlockNumber::Latest)
         .await
         .unwrap()
         .unwrap();
     assert_eq!(block_a.hash, block_b.hash.unwrap());
+    assert_eq!(block_a.parent_hash, block_b.parent_hash);
+    assert_eq!(block_a.timestamp, block_b.timestamp);
+}
+
+/// Test that block collector detects reorgs.
+#[tokio::test]
+async fn test_block_collector_detects_reorgs() {
+    let anvil = Anvil::new().spawn();
+    let provider = Provider::<Ws>::connect(anvil.ws_endpoint()).await.unwrap();
+    let provider = Arc::new(provider);
+    let block_collector = BlockCollector::new(provider.clone());
+    let mut block_stream = block_collector.get_event_stream().await.unwrap();
+
+    let snapshot: U256 = provider.request("evm_snapshot", ()).await.unwrap();
+    for _ in 0..2 {
+        let _: U256 = provider.request("evm_mine", ()).await.unwrap();
+    }
+    for number in 1..=2u64 {
+        match block_stream.next().await.unwrap() {
+            BlockEvent::NewBlock(block) => assert_eq!(block.number, number.into()),
+            event => panic!("unexpected event {:?}", event),
+        }
+    }
+
+    // Replace blocks 1 and 2 with a longer branch.
+    let _: bool = provider.request("evm_revert", [snapshot]).await.unwrap();
+    let timestamp = provider
+        .get_block(BlockNumber::Latest)
+        .await
+        .unwrap()
+        .unwrap()
+        .timestamp;
+    for offset in 1..=3u64 {
+        let _: U256 = provider
+            .request("evm_mine", [timestamp + 100 * offset])
+            .await
+            .unwrap();
+    }
+    let reorg = match block_stream.next().await.unwrap() {
+        BlockEvent::Reorg(reorg) => reorg,
+        event => panic!("unexpected event {:?}", event),
+    };
+    assert_eq!(reorg.depth, 2);
+    assert_eq!(reorg.old_blocks[0].number, 1.into());
+    assert_eq!(reorg.new_blocks[0].number, 1.into());
+}
+
+/// Test that block collector backfills from its start block, then resumes from the block its consumer committed.
+#[tokio::test]
+async fn test_block_collector_backfills_and_resumes() {
+    let dir = std::env::temp_dir().join(format!("arbot-checkpoints-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    let anvil = Anvil::new().spawn();
+    let provider = Provider::<Ws>::connect(anvil.ws_endpoint()).await.unwrap();
+    let provider = Arc::new(provider);
+    for _ in 0..3 {
+        let _: U256 = provider.request("evm_mine", ()).await.unwrap();
+    }
+
+    let checkpoint =
+        BlockCheckpoint::new(&SnapshotStore::new(&dir), "blocks").with_interval(Duration::ZERO);
+    let block_collector = BlockCollector::new(provider.clone())
+        .with_start_block(1u64)
+        .with_checkpoint(checkpoint.clone());
+    let mut block_stream = block_collector.get_event_stream().await.unwrap();
+    let _: U256 = provider.request("evm_mine", ()).await.unwrap();
+    for number in 1..=4u64 {
+        match block_stream.next().await.unwrap() {
+            BlockEvent::NewBlock(block) => {
+                assert_eq!(block.number, number.into());
+                // Only the first three blocks are processed before the bot stops.
+                if number < 4 {
+                    checkpoint.commit(&block).await;
+                }
+            }
+            event => panic!("unexpected event {:?}", event),
+        }
+    }
+    drop(block_stream);
+    // Checkpoints are kept apart from strategy snapshots.
+    assert!(dir.join("checkpoints").join("blocks.json").exists());
+    assert!(!dir.join("blocks.json").exists());
+
+    // The unprocessed block and blocks mined while the collector is down are
+    // emitted after the checkpoint.
+    for _ in 0..2 {
+        let _: U256 = provider.request("evm_mine", ()).await.unwrap();
+    }
+    let block_collector = BlockCollector::new(provider.clone())
+        .with_start_block(1u64)
+        .with_checkpoint(BlockCheckpoint::new(&SnapshotStore::new(&dir), "blocks"));
+    let mut block_stream = block_collector.get_event_stream().await.unwrap();
+    for number in 4..=6u64 {
+        match block_stream.next().await.unwrap() {
+            BlockEvent::NewBlock(block) => assert_eq!(block.number, number.into()),
+            event => panic!("unexpected event {:?}", event),
+        }
+    }
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+/// Test that block checkpoint writes are throttled to its interval, and the
+/// last throttled block is written once the interval elapses.
+#[tokio::test]
+async fn test_block_checkpoint_throttles_writes() {
+    let dir = std::env::temp_dir().join(format!(
+        "arbot-throttled-checkpoints-{}",
+        std::process::id()
+    ));
+    let _ = std::fs::remove_dir_all(&dir);
+    let checkpoint = BlockCheckpoint::new(&SnapshotStore::new(&dir), "blocks")
+        .with_interval(Duration::from_millis(100));
+    let block = |number: u64| NewBlock {
+        hash: H256::from_low_u64_be(number),
+        number: number.into(),
+        parent_hash: H256::from_low_u64_be(number - 1),
+        timestamp: U256::zero(),
+        base_fee_per_gas: None,
+        gas_used: U256::zero(),
+        gas_limit: U256::zero(),
+        logs_bloom: None,
+    };
+    assert!(checkpoint.load().await.is_none());
+
+    checkpoint.commit(&block(1)).await;
+    checkpoint.commit(&block(2)).await;
+    checkpoint.commit(&block(3)).await;
+    assert_eq!(checkpoint.load().await.unwrap().number, 1.into());
+
+    sleep(Duration::from_millis(150)).await;
+    assert_eq!(checkpoint.load().await.unwrap().number, 3.into());
+    checkpoint.commit(&block(4)).await;
+    checkpoint.flush().await;
+    assert_eq!(checkpoint.load().await.unwrap().number, 4.into());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+/// Test that a restart right after a throttled commit resumes after the
+/// committed block.
+#[test]
+fn test_block_checkpoint_writes_throttled_block_on_shutdown() {
+    let dir =
+        std::env::temp_dir().join(format!("arbot-shutdown-checkpoints-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    let block = |number: u64| NewBlock {
+        hash: H256::from_low_u64_be(number),
+        number: number.into(),
+        parent_hash: H256::from_low_u64_be(number - 1),
+        timestamp: U256::zero(),
+        base_fee_per_gas: None,
+        gas_used: U256::zero(),
+        gas_limit: U256::zero(),
+        logs_bloom: None,
+    };
+
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    runtime.block_on(async {
+        let checkpoint = BlockCheckpoint::new(&SnapshotStore::new(&dir), "blocks")
+            .with_interval(Duration::from_secs(60));
+        checkpoint.commit(&block(1)).await;
+        checkpoint.commit(&block(2)).await;
+    });
+    // The bot stops before the interval elapses.
+    drop(runtime);
+
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    runtime.block_on(async {
+        let checkpoint = BlockCheckpoint::new(&SnapshotStore::new(&dir), "blocks");
+        assert_eq!(checkpoint.load().await.unwrap().number, 2.into());
+    });
+    std::fs::remove_dir_all(&dir).unwrap();
 }
 
 /// Test that mempool collector correctly emits blocks.
@@ -61,6 +277,282 @@ async fn test_mempool_collector_sends_txs() {
     assert_eq!(tx.value, value.into());
 }
 
+/// Test that block and mempool collectors poll over HTTP.
+#[tokio::test]
+async fn test_collectors_poll_over_http() {
+    let anvil = Anvil::new().block_time(1u64).spawn();
+    let provider = Provider::<Http>::try_from(anvil.endpoint())
+        .unwrap()
+        .interval(Duration::from_millis(50u64));
+    let provider = Arc::new(provider);
+
+    let block_collector = BlockCollector::polling(provider.clone());
+    let mut block_stream = block_collector.get_event_stream().await.unwrap();
+    assert!(matches!(
+        block_stream.next().await.unwrap(),
+        BlockEvent::NewBlock(_)
+    ));
+
+    let mempool_collector = MempoolCollector::polling(provider.clone());
+    let mut mempool_stream = mempool_collector.get_event_stream().await.unwrap();
+    let account = provider.get_accounts().await.unwrap()[0];
+    let tx = TransactionRequest::new()
+        .to(account)
+        .from(account)
+        .value(42u64);
+    provider.send_transaction(tx, None).await.unwrap();
+    let tx = mempool_stream.next().await.unwrap();
+    assert_eq!(tx.value, 42.into());
+}
+
+/// Call decoded by the mempool collector tests.
+#[derive(Debug, Clone, EthCall)]
+struct SwapCall {
+    amount: U256,
+}
+
+/// Test that mempool filters and decoding select the expected transactions.
+#[test]
+fn test_mempool_filter_and_decoding() {
+    let router = Address::repeat_byte(1);
+    let sender = Address::repeat_byte(2);
+    let other = Address::repeat_byte(3);
+    let tx = Transaction {
+        to: Some(router),
+        from: sender,
+        value: 100.into(),
+        input: SwapCall { amount: 7.into() }.encode().into(),
+        ..Default::default()
+    };
+    assert!(MempoolFilter::new().matches(&tx));
+
+    let filter = MempoolFilter::new()
+        .to([router])
+        .selectors([SwapCall::selector()])
+        .min_value(100u64);
+    assert!(filter.matches(&tx));
+    assert!(!filter.clone().min_value(101u64).matches(&tx));
+    assert!(!filter.clone().deny_senders([sender]).matches(&tx));
+    assert!(!filter.clone().allow_senders([other]).matches(&tx));
+    assert!(!MempoolFilter::new().to([other]).matches(&tx));
+    assert!(!MempoolFilter::new().selectors([[0; 4]]).matches(&tx));
+
+    let decoded = DecodedPendingTx::<SwapCall>::decode(tx.clone()).unwrap();
+    assert_eq!(decoded.call.amount, 7.into());
+    let transfer = Transaction {
+        input: Bytes::from(vec![0; 36]),
+        ..tx
+    };
+    assert!(DecodedPendingTx::<SwapCall>::decode(transfer).is_none());
+}
+
+/// Event emitted by the contracts deployed with [deploy_pinger].
+#[derive(Debug, Clone, EthEvent)]
+struct Ping {
+    value: U256,
+}
+
+/// Deploys a contract whose constructor emits `Ping(42)`, and returns its
+/// address.
+async fn deploy_pinger(provider: &Provider<Ws>) -> Address {
+    let account = provider.get_accounts().await.unwrap()[0];
+    // mstore(0, 42); log1(0, 32, topic)
+    let mut code = vec![0x60, 0x2a, 0x60, 0x00, 0x52, 0x7f];
+    code.extend_from_slice(Ping::signature().as_bytes());
+    code.extend_from_slice(&[0x60, 0x20, 0x60, 0x00, 0xa1, 0x00]);
+    let tx = TransactionRequest::new()
+        .from(account)
+        .data(Bytes::from(code));
+    let receipt = provider
+        .send_transaction(tx, None)
+        .await
+        .unwrap()
+        .await
+        .unwrap()
+        .unwrap();
+    receipt.contract_address.unwrap()
+}
+
+/// Test that log collector backfills logs and follows address updates.
+#[tokio::test]
+async fn test_log_collector_backfills_and_follows_addresses() {
+    let (provider, _anvil) = spawn_anvil().await;
+    let provider = Arc::new(provider);
+    let first = deploy_pinger(&provider).await;
+    let addresses = LogAddresses::new([first]);
+    let log_collector = LogCollector::new(provider.clone(), Filter::new())
+        .with_addresses(addresses.clone())
+        .with_backfill_from(0u64)
+        .decoded::<Ping>();
+    let mut log_stream = log_collector.get_event_stream().await.unwrap();
+
+    let log = log_stream.next().await.unwrap();
+    assert_eq!(log.meta.address, first);
+    assert_eq!(log.event.value, 42.into());
+
+    let second = deploy_pinger(&provider).await;
+    addresses.insert(second);
+    let log = log_stream.next().await.unwrap();
+    assert_eq!(log.meta.address, second);
+}
+
+/// Test that log collector backfills an address added from a given block.
+#[tokio::test]
+async fn test_log_collector_backfills_added_addresses_from_block() {
+    // Mine on demand, so that block numbers are predictable.
+    let anvil = Anvil::new().spawn();
+    let provider = Provider::<Ws>::connect(anvil.ws_endpoint()).await.unwrap();
+    let provider = Arc::new(provider.interval(Duration::from_millis(50u64)));
+    let addresses = LogAddresses::new([deploy_pinger(&provider).await]);
+    let log_collector = LogCollector::new(provider.clone(), Filter::new())
+        .with_addresses(addresses.clone())
+        .decoded::<Ping>();
+    let mut log_stream = log_collector.get_event_stream().await.unwrap();
+
+    let second = deploy_pinger(&provider).await;
+    let deployed_at = provider.get_block_number().await.unwrap();
+    for _ in 0..2 {
+        let _: U256 = provider.request("evm_mine", ()).await.unwrap();
+    }
+    addresses.insert_from(second, deployed_at);
+    let log = log_stream.next().await.unwrap();
+    assert_eq!(log.meta.address, second);
+    assert_eq!(log.meta.block_number, deployed_at);
+}
+
+/// Test that log collector emits logs of a new branch at positions it
+/// already emitted logs for before a reorg.
+#[tokio::test]
+async fn test_log_collector_emits_logs_after_reorgs() {
+    // Mine on demand, so that block numbers are predictable.
+    let anvil = Anvil::new().spawn();
+    let provider = Provider::<Ws>::connect(anvil.ws_endpoint()).await.unwrap();
+    let provider = Arc::new(provider.interval(Duration::from_millis(50u64)));
+    let log_collector = LogCollector::new(provider.clone(), Filter::new()).decoded::<Ping>();
+    let mut log_stream = log_collector.get_event_stream().await.unwrap();
+
+    let snapshot: U256 = provider.request("evm_snapshot", ()).await.unwrap();
+    for _ in 0..2 {
+        let _: U256 = provider.request("evm_mine", ()).await.unwrap();
+    }
+    let pinger = deploy_pinger(&provider).await;
+    let log = log_stream.next().await.unwrap();
+    assert_eq!(log.meta.block_number, 3.into());
+
+    // Deploy the same contract on a shorter branch, so that its log lands
+    // in an earlier block.
+    let _: bool = provider.request("evm_revert", [snapshot]).await.unwrap();
+    assert_eq!(deploy_pinger(&provider).await, pinger);
+    let log = loop {
+        let log = log_stream.next().await.unwrap();
+        if !log.removed {
+            break log;
+        }
+    };
+    assert_eq!(log.meta.address, pinger);
+    assert_eq!(log.meta.block_number, 1.into());
+}
+
+/// Test that the Seaport event ABI matches the deployed Seaport events.
+#[test]
+fn test_seaport_event_signatures() {
+    assert_eq!(
+        OrderFulfilledFilter::signature(),
+        H256(keccak256(
+            "OrderFulfilled(bytes32,address,address,address,(uint8,address,uint256,uint256)[],(uint8,address,uint256,uint256,address)[])"
+        ))
+    );
+    assert_eq!(
+        OrderCancelledFilter::signature(),
+        H256(keccak256("OrderCancelled(bytes32,address,address)"))
+    );
+    assert_eq!(
+        OrderValidated1Filter::signature(),
+        H256(keccak256("OrderValidated(bytes32,address,address)"))
+    );
+    assert_eq!(
+        OrderValidated2Filter::signature(),
+        H256(keccak256(
+            "OrderValidated(bytes32,(address,address,(uint8,address,uint256,uint256,uint256)[],(uint8,address,uint256,uint256,uint256,address)[],uint8,uint256,uint256,bytes32,uint256,bytes32,uint256))"
+        ))
+    );
+    assert_eq!(
+        CounterIncrementedFilter::signature(),
+        H256(keccak256("CounterIncremented(uint256,address)"))
+    );
+}
+
+/// Test that Seaport 1.4 order validations are decoded with their parameters.
+#[test]
+fn test_seaport_event_decodes_order_parameters() {
+    let validated = OrderValidated2Filter {
+        order_hash: [7; 32],
+        order_parameters: (
+            Address::repeat_byte(1),
+            Address::repeat_byte(2),
+            vec![],
+            vec![],
+            0,
+            1.into(),
+            2.into(),
+            [0; 32],
+            3.into(),
+            [0; 32],
+            0.into(),
+        ),
+    };
+    let log = RawLog {
+        topics: vec![OrderValidated2Filter::signature()],
+        data: encode(&[
+            validated.order_hash.into_token(),
+            validated.order_parameters.clone().into_token(),
+        ]),
+    };
+    match SeaportEvents::decode_log(&log).unwrap() {
+        SeaportEvents::OrderValidated2Filter(event) => assert_eq!(event, validated),
+        event => panic!("unexpected event {:?}", event),
+    }
+}
+
+/// Test that Seaport event collector decodes events emitted by the given
+/// deployments.
+#[tokio::test]
+async fn test_seaport_event_collector_decodes_events() {
+    let (provider, _anvil) = spawn_anvil().await;
+    let provider = Arc::new(provider);
+    let account = provider.get_accounts().await.unwrap()[0];
+    // mstore(0, 7); log2(0, 32, signature, offerer)
+    let mut code = vec![0x60, 0x07, 0x60, 0x00, 0x52, 0x7f];
+    code.extend_from_slice(H256::from(account).as_bytes());
+    code.push(0x7f);
+    code.extend_from_slice(CounterIncrementedFilter::signature().as_bytes());
+    code.extend_from_slice(&[0x60, 0x20, 0x60, 0x00, 0xa2, 0x00]);
+    let tx = TransactionRequest::new()
+        .from(account)
+        .data(Bytes::from(code));
+    let receipt = provider
+        .send_transaction(tx, None)
+        .await
+        .unwrap()
+        .await
+        .unwrap()
+        .unwrap();
+    let seaport = receipt.contract_address.unwrap();
+
+    let seaport_collector = SeaportEventCollector::with_deployments(provider.clone(), [seaport])
+        .with_backfill_from(0u64);
+    let mut event_stream = seaport_collector.get_event_stream().await.unwrap();
+    let log = event_stream.next().await.unwrap();
+    assert_eq!(log.meta.address, seaport);
+    match log.event {
+        SeaportEvents::CounterIncrementedFilter(event) => {
+            assert_eq!(event.new_counter, 7.into());
+            assert_eq!(event.offerer, account);
+        }
+        event => panic!("unexpected event {:?}", event),
+    }
+}
+
 /// Test that the mempool executor correctly sends txs
 #[tokio::test]
 async fn test_mempool_executor_sends_tx_simple() {
@@ -86,3 +578,1570 @@ async fn test_mempool_executor_sends_tx_simple() {
     let tx = provider.get_transaction_count(account, None).await.unwrap();
     assert_eq!(tx, 1.into());
 }
+
+/// Test that the mempool executor stops waiting for receipts after its timeout
+#[tokio::test]
+async fn test_mempool_executor_times_out_waiting_for_receipt() {
+    let anvil = Anvil::new().arg("--no-mining").spawn();
+    let provider = Provider::<Ws>::connect(anvil.ws_endpoint()).await.unwrap();
+    let provider = Arc::new(provider.interval(Duration::from_millis(10u64)));
+    let mempool_executor = MempoolExecutor::new(provider.clone())
+        .with_receipts(true)
+        .with_receipt_timeout(Duration::from_millis(200));
+
+    let account = provider.get_accounts().await.unwrap()[0];
+    let action = SubmitTxToMempool {
+        tx: TransactionRequest::new()
+            .to(account)
+            .from(account)
+            .value(42u64)
+            .into(),
+        gas_bid_info: None,
+    };
+    let execution = timeout(Duration::from_secs(5), mempool_executor.execute(action))
+        .await
+        .unwrap()
+        .unwrap()
+        .unwrap();
+    assert!(execution.tx_hash.is_some());
+    assert_eq!(execution.block_number, None);
+}
+
+/// Test that the simulation executor simulates txs without sending them
+#[tokio::test]
+async fn test_simulation_executor_does_not_send_tx() {
+    let (provider, _anvil) = spawn_anvil().await;
+    let provider = Arc::new(provider);
+    let simulation_executor = SimulationExecutor::new(provider.clone()).with_outcome_capacity(1);
+
+    let account = provider.get_accounts().await.unwrap()[0];
+    for value in [42u64, 43] {
+        let tx = TransactionRequest::new()
+            .to(account)
+            .from(account)
+            .value(value);
+        let action = SubmitTxToMempool {
+            tx: tx.into(),
+            gas_bid_info: None,
+        };
+        simulation_executor.execute(action).await.unwrap();
+    }
+
+    // Only the latest outcome is kept.
+    let outcomes = simulation_executor.outcomes();
+    assert_eq!(outcomes.len(), 1);
+    assert_eq!(outcomes[0].tx.value(), Some(&43.into()));
+    assert!(outcomes[0].success);
+    assert!(outcomes[0].gas_used.is_some());
+    let tx = provider.get_transaction_count(account, None).await.unwrap();
+    assert_eq!(tx, 0.into());
+}
+
+/// Test that the simulation executor fails, rather than reporting a revert,
+/// when the node cannot be reached.
+#[tokio::test]
+async fn test_simulation_executor_fails_on_transport_errors() {
+    let provider = Arc::new(Provider::<Http>::try_from("http://127.0.0.1:1").unwrap());
+    let simulation_executor = SimulationExecutor::new(provider);
+
+    let action = SubmitTxToMempool {
+        tx: TransactionRequest::new().to(Address::zero()).into(),
+        gas_bid_info: None,
+    };
+    assert!(simulation_executor.execute(action).await.is_err());
+    assert!(simulation_executor.outcomes().is_empty());
+}
+
+/// Test that the simulation executor computes the sender's balance delta
+/// from `trace_call`, and from `debug_traceCall` on nodes without `trace_call`.
+#[tokio::test]
+async fn test_simulation_executor_computes_balance_delta() {
+    let (provider, mock) = Provider::mocked();
+    let simulation_executor = SimulationExecutor::new(Arc::new(provider));
+    let sender = Address::repeat_byte(1);
+    let action = || SubmitTxToMempool {
+        tx: TransactionRequest::new()
+            .from(sender)
+            .to(Address::repeat_byte(2))
+            .value(42)
+            .into(),
+        gas_bid_info: None,
+    };
+
+    // Responses are served last pushed first.
+    mock.push(json!({
+        "output": "0x",
+        "stateDiff": {
+            format!("{:?}", sender): {
+                "balance": { "*": { "from": "0x64", "to": "0x10" } },
+                "nonce": "=",
+                "code": "=",
+                "storage": {}
+            }
+        }
+    }))
+    .unwrap();
+    mock.push(U256::from(21_000)).unwrap();
+    mock.push::<Bytes, _>(Bytes::new()).unwrap();
+    simulation_executor.execute(action()).await.unwrap();
+
+    mock.push(json!({
+        "pre": { format!("{:?}", sender): { "balance": "0x64", "nonce": 1 } },
+        "post": { format!("{:?}", sender): { "balance": "0x20", "nonce": 2 } }
+    }))
+    .unwrap();
+    mock.push_response(MockResponse::Error(JsonRpcError {
+        code: -32601,
+        message: "the method trace_call does not exist/is not available".to_string(),
+        data: None,
+    }));
+    mock.push(U256::from(21_000)).unwrap();
+    mock.push::<Bytes, _>(Bytes::new()).unwrap();
+    simulation_executor.execute(action()).await.unwrap();
+
+    let outcomes = simulation_executor.outcomes();
+    assert_eq!(outcomes[0].balance_delta, Some(I256::from(-84)));
+    assert_eq!(outcomes[1].balance_delta, Some(I256::from(-68)));
+}
+
+/// Test that the simulation executor reports the reason of a reverted call.
+#[tokio::test]
+async fn test_simulation_executor_reports_revert_reason() {
+    let (provider, mock) = Provider::mocked();
+    let simulation_executor = SimulationExecutor::new(Arc::new(provider));
+    let action = SubmitTxToMempool {
+        tx: TransactionRequest::new().to(Address::repeat_byte(2)).into(),
+        gas_bid_info: None,
+    };
+
+    let mut revert_data = vec![0x08, 0xc3, 0x79, 0xa0];
+    revert_data.extend("not profitable".to_string().encode());
+    mock.push_response(MockResponse::Error(JsonRpcError {
+        code: 3,
+        message: "execution reverted: not profitable".to_string(),
+        data: Some(json!(Bytes::from(revert_data))),
+    }));
+    let execution = simulation_executor.execute(action).await.unwrap().unwrap();
+
+    assert!(execution.reverted);
+    let outcomes = simulation_executor.outcomes();
+    assert!(!outcomes[0].success);
+    assert_eq!(outcomes[0].revert_reason.as_deref(), Some("not profitable"));
+    assert_eq!(outcomes[0].gas_used, None);
+    assert_eq!(outcomes[0].balance_delta, None);
+}
+
+/// Test that the simulation executor checks block deadlines against the latest block.
+#[tokio::test]
+async fn test_simulation_executor_checks_block_deadlines() {
+    let (provider, mock) = Provider::mocked();
+    let simulation_executor = SimulationExecutor::new(Arc::new(provider));
+    let deadline = Deadline::Block(10.into());
+
+    mock.push(U64::from(9)).unwrap();
+    assert!(!Executor::<SubmitTxToMempool>::is_expired(&simulation_executor, &deadline).await);
+    mock.push(U64::from(10)).unwrap();
+    assert!(Executor::<SubmitTxToMempool>::is_expired(&simulation_executor, &deadline).await);
+}
+
+/// A collector that emits a fixed list of events, then stays idle.
+struct VecCollector {
+    events: Vec<u64>,
+}
+
+#[async_trait]
+impl Collector<u64> for VecCollector {
+    async fn get_event_stream(&self) -> Result<CollectorStream<u64>> {
+        let stream = futures::stream::iter(self.events.clone()).chain(futures::stream::pending());
+        Ok(Box::pin(stream))
+    }
+}
+
+/// Waits until `condition` holds, failing the test if it does not within
+/// five seconds.
+async fn wait_until(condition: impl Fn() -> bool) {
+    timeout(Duration::from_secs(5), async {
+        while !condition() {
+            sleep(Duration::from_millis(5)).await;
+        }
+    })
+    .await
+    .expect("timed out waiting for condition");
+}
+
+/// Shuts the engine down, failing the test if its tasks do not finish within
+/// five seconds.
+async fn shut_down(shutdown: ShutdownHandle, mut set: JoinSet<()>) {
+    shutdown.shutdown();
+    timeout(Duration::from_secs(5), async {
+        while let Some(res) = set.join_next().await {
+            res.unwrap();
+        }
+    })
+    .await
+    .expect("timed out waiting for the engine to stop");
+}
+
+/// A strategy that turns every event into an action.
+#[derive(Default)]
+struct EchoStrategy {
+    torn_down: Arc<Mutex<bool>>,
+}
+
+#[async_trait]
+impl Strategy<u64, u64> for EchoStrategy {
+    async fn sync_state(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    async fn process_event(&mut self, event: u64) -> Result<Vec<u64>> {
+        Ok(vec![event])
+    }
+
+    async fn teardown(&mut self) -> Result<()> {
+        *self.torn_down.lock().unwrap() = true;
+        Ok(())
+    }
+}
+
+/// An executor that records every action it receives.
+#[derive(Default)]
+struct RecordingExecutor {
+    actions: Arc<Mutex<Vec<u64>>>,
+    torn_down: Arc<Mutex<bool>>,
+}
+
+#[async_trait]
+impl Executor<u64> for RecordingExecutor {
+    async fn execute(&self, action: u64) -> Result<Option<Execution>> {
+        self.actions.lock().unwrap().push(action);
+        Ok(Some(Execution::default()))
+    }
+
+    async fn teardown(&self) -> Result<()> {
+        *self.torn_down.lock().unwrap() = true;
+        Ok(())
+    }
+}
+
+/// Test that the engine drains queued work and tears down components on shutdown.
+#[tokio::test]
+async fn test_engine_graceful_shutdown() {
+    let strategy = EchoStrategy::default();
+    let strategy_torn_down = strategy.torn_down.clone();
+    let executor = RecordingExecutor::default();
+    let actions = executor.actions.clone();
+    let executor_torn_down = executor.torn_down.clone();
+
+    let mut engine: Engine<u64, u64> = Engine::new();
+    engine
+        .add_collector(Box::new(VecCollector {
+            events: vec![1, 2, 3],
+        }))
+        .unwrap();
+    engine.add_strategy(Box::new(strategy)).unwrap();
+    engine.add_executor(Box::new(executor)).unwrap();
+    let shutdown = engine.shutdown_handle();
+
+    let set = engine.run().await.unwrap();
+    wait_until(|| actions.lock().unwrap().len() == 3).await;
+    shut_down(shutdown, set).await;
+
+    assert_eq!(*actions.lock().unwrap(), vec![1, 2, 3]);
+    assert!(*strategy_torn_down.lock().unwrap());
+    assert!(*executor_torn_down.lock().unwrap());
+}
+
+/// A collector that fails to produce an event stream on its first two calls.
+#[derive(Default)]
+struct FlakyCollector {
+    calls: AtomicU64,
+}
+
+#[async_trait]
+impl Collector<u64> for FlakyCollector {
+    async fn get_event_stream(&self) -> Result<CollectorStream<u64>> {
+        let call = self.calls.fetch_add(1, Ordering::SeqCst);
+        if call < 2 {
+            anyhow::bail!("connection refused");
+        }
+        let stream = futures::stream::iter(vec![call]).chain(futures::stream::pending());
+        Ok(Box::pin(stream))
+    }
+}
+
+/// Test that the engine restarts collectors that fail to start.
+#[tokio::test]
+async fn test_engine_restarts_collectors() {
+    let executor = RecordingExecutor::default();
+    let actions = executor.actions.clone();
+
+    let mut engine: Engine<u64, u64> = Engine::new();
+    let policy = RestartPolicy {
+        max_restarts: Some(2),
+        initial_backoff: Duration::from_millis(10),
+        ..RestartPolicy::default()
+    };
+    engine
+        .add_collector_with_policy(Box::new(FlakyCollector::default()), policy)
+        .unwrap();
+    engine
+        .add_strategy(Box::new(EchoStrategy::default()))
+        .unwrap();
+    engine.add_executor(Box::new(executor)).unwrap();
+    let shutdown = engine.shutdown_handle();
+
+    let set = engine.run().await.unwrap();
+    wait_until(|| !actions.lock().unwrap().is_empty()).await;
+    shut_down(shutdown, set).await;
+
+    assert_eq!(*actions.lock().unwrap(), vec![2]);
+}
+
+/// Test that a collector which exceeds its restart limit is reported unhealthy.
+#[tokio::test]
+async fn test_engine_reports_collectors_exceeding_restart_limit() {
+    let mut engine: Engine<u64, u64> = Engine::new();
+    let policy = RestartPolicy {
+        max_restarts: Some(1),
+        initial_backoff: Duration::from_millis(10),
+        ..RestartPolicy::default()
+    };
+    engine
+        .add_collector_with_policy(Box::new(FlakyCollector::default()), policy)
+        .unwrap();
+    let health = engine.health();
+    let shutdown = engine.shutdown_handle();
+
+    let set = engine.run().await.unwrap();
+    // The collector gives up after its first restart, well before shutdown.
+    sleep(Duration::from_millis(100)).await;
+    shut_down(shutdown, set).await;
+    let failed = Health::Unhealthy("error getting event stream: connection refused".to_string());
+    assert_eq!(health.get("FlakyCollector"), Some(failed));
+}
+
+/// A strategy that processes events slowly and resyncs when it lags.
+#[derive(Default)]
+struct SlowStrategy {
+    syncs: Arc<AtomicU64>,
+}
+
+#[async_trait]
+impl Strategy<u64, u64> for SlowStrategy {
+    async fn sync_state(&mut self) -> Result<()> {
+        self.syncs.fetch_add(1, Ordering::SeqCst);
+        Ok(())
+    }
+
+    async fn process_event(&mut self, _event: u64) -> Result<Vec<u64>> {
+        sleep(Duration::from_millis(10)).await;
+        Ok(vec![])
+    }
+
+    fn on_lag(&mut self, _skipped: u64) -> LagPolicy {
+        LagPolicy::Resync
+    }
+}
+
+/// Test that lagging strategies are counted and resynced.
+#[tokio::test]
+async fn test_engine_resyncs_lagging_strategy() {
+    let strategy = SlowStrategy::default();
+    let syncs = strategy.syncs.clone();
+
+    let mut engine: Engine<u64, u64> = Engine::new().with_event_channel_capacity(4);
+    engine
+        .add_collector(Box::new(VecCollector {
+            events: (0..100).collect(),
+        }))
+        .unwrap();
+    engine.add_strategy(Box::new(strategy)).unwrap();
+    let lag_counters = engine.lag_counters();
+    let shutdown = engine.shutdown_handle();
+
+    let set = engine.run().await.unwrap();
+    wait_until(|| lag_counters.get("SlowStrategy") > 0 && syncs.load(Ordering::SeqCst) > 1).await;
+    shut_down(shutdown, set).await;
+
+    assert!(lag_counters.get("SlowStrategy") > 0);
+    assert!(syncs.load(Ordering::SeqCst) > 1);
+}
+
+/// A strategy that emits two actions per event, plus a delayed one from a
+/// background task.
+#[derive(Default)]
+struct FanOutStrategy {
+    sink: Option<ActionSink<u64>>,
+}
+
+#[async_trait]
+impl Strategy<u64, u64> for FanOutStrategy {
+    async fn sync_state(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    async fn process_event(&mut self, event: u64) -> Result<Vec<u64>> {
+        let sink = self.sink.clone().unwrap();
+        tokio::spawn(async move {
+            sleep(Duration::from_millis(10)).await;
+            sink.send(event * 100).unwrap();
+        });
+        Ok(vec![event, event * 10])
+    }
+
+    fn set_action_sink(&mut self, sink: ActionSink<u64>) {
+        self.sink = Some(sink);
+    }
+}
+
+/// Test that strategies can emit several actions, including from background tasks.
+#[tokio::test]
+async fn test_engine_strategy_emits_many_actions() {
+    let executor = RecordingExecutor::default();
+    let actions = executor.actions.clone();
+
+    let mut engine: Engine<u64, u64> = Engine::new();
+    engine
+        .add_collector(Box::new(VecCollector { events: vec![1] }))
+        .unwrap();
+    engine
+        .add_strategy(Box::new(FanOutStrategy::default()))
+        .unwrap();
+    engine.add_executor(Box::new(executor)).unwrap();
+    let shutdown = engine.shutdown_handle();
+
+    let set = engine.run().await.unwrap();
+    wait_until(|| actions.lock().unwrap().len() == 3).await;
+    shut_down(shutdown, set).await;
+
+    assert_eq!(*actions.lock().unwrap(), vec![1, 10, 100]);
+}
+
+/// A strategy that fails on odd events, and whose first resync fails too.
+#[derive(Default)]
+struct FaultyStrategy {
+    syncs: Arc<AtomicU64>,
+}
+
+#[async_trait]
+impl Strategy<u64, u64> for FaultyStrategy {
+    async fn sync_state(&mut self) -> Result<()> {
+        if self.syncs.fetch_add(1, Ordering::SeqCst) == 1 {
+            anyhow::bail!("node unavailable");
+        }
+        Ok(())
+    }
+
+    async fn process_event(&mut self, event: u64) -> Result<Vec<u64>> {
+        if event % 2 == 1 {
+            anyhow::bail!("out of sync");
+        }
+        Ok(vec![event])
+    }
+}
+
+/// Test that a failing strategy is resynced instead of bringing down the
+/// engine, and processes the events queued in the meantime.
+#[tokio::test]
+async fn test_engine_resyncs_failed_strategy() {
+    let strategy = FaultyStrategy::default();
+    let syncs = strategy.syncs.clone();
+    let executor = RecordingExecutor::default();
+    let actions = executor.actions.clone();
+
+    let mut engine: Engine<u64, u64> = Engine::new().with_resync_policy(RestartPolicy {
+        initial_backoff: Duration::from_millis(10),
+        ..RestartPolicy::default()
+    });
+    engine
+        .add_collector(Box::new(VecCollector {
+            events: vec![1, 2, 4],
+        }))
+        .unwrap();
+    engine.add_strategy(Box::new(strategy)).unwrap();
+    engine.add_executor(Box::new(executor)).unwrap();
+    let health = engine.health();
+    let shutdown = engine.shutdown_handle();
+
+    let set = engine.run().await.unwrap();
+    wait_until(|| actions.lock().unwrap().len() == 2).await;
+    assert_eq!(syncs.load(Ordering::SeqCst), 3);
+    assert_eq!(health.get("FaultyStrategy"), Some(Health::Healthy));
+
+    shut_down(shutdown, set).await;
+    assert_eq!(*actions.lock().unwrap(), vec![2, 4]);
+    assert_eq!(health.get("FaultyStrategy"), Some(Health::Stopped));
+}
+
+/// Test that a strategy whose resync is given up on is reported unhealthy.
+#[tokio::test]
+async fn test_engine_reports_failed_resync() {
+    let mut engine: Engine<u64, u64> = Engine::new().with_resync_policy(RestartPolicy {
+        max_restarts: Some(0),
+        ..RestartPolicy::default()
+    });
+    engine
+        .add_collector(Box::new(VecCollector { events: vec![1] }))
+        .unwrap();
+    engine
+        .add_strategy(Box::new(FaultyStrategy::default()))
+        .unwrap();
+    let health = engine.health();
+    let shutdown = engine.shutdown_handle();
+
+    let set = engine.run().await.unwrap();
+    wait_until(|| {
+        matches!(health.get("FaultyStrategy"), Some(Health::Unhealthy(reason)) if reason.starts_with("resync failed"))
+    })
+    .await;
+    shut_down(shutdown, set).await;
+    assert!(matches!(
+        health.get("FaultyStrategy"),
+        Some(Health::Unhealthy(_))
+    ));
+}
+
+/// Test that recorded events are replayed in order, without the recorded
+/// execution reports.
+#[tokio::test]
+async fn test_engine_records_and_replays_events() {
+    let path = std::env::temp_dir().join(format!("arbot-events-{}.jsonl", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+
+    let mut engine: Engine<u64, u64> = Engine::new();
+    engine
+        .add_collector(Box::new(VecCollector {
+            events: vec![3, 1, 2],
+        }))
+        .unwrap();
+    engine
+        .add_strategy(Box::new(EchoStrategy::default()))
+        .unwrap();
+    engine
+        .add_executor(Box::new(RecordingExecutor::default()))
+        .unwrap();
+    engine.report_executions(|report| (report.action < 1000).then(|| report.action + 1000));
+    engine.record_events(EventRecorder::new(&path));
+    let shutdown = engine.shutdown_handle();
+    let set = engine.run().await.unwrap();
+    // three collected events and their three execution reports
+    wait_until(|| {
+        std::fs::read_to_string(&path).is_ok_and(|recording| recording.lines().count() == 6)
+    })
+    .await;
+    shut_down(shutdown, set).await;
+
+    let executor = RecordingExecutor::default();
+    let actions = executor.actions.clone();
+    let mut engine: Engine<u64, u64> = Engine::new();
+    let replay_collector = ReplayCollector::new(&path, ReplaySpeed::Accelerated(10.0));
+    engine
+        .add_collector_with_policy(Box::new(replay_collector), RestartPolicy::never())
+        .unwrap();
+    engine
+        .add_strategy(Box::new(EchoStrategy::default()))
+        .unwrap();
+    engine.add_executor(Box::new(executor)).unwrap();
+
+    // The engine stops on its own once the recording is exhausted.
+    let mut set = engine.run().await.unwrap();
+    tokio::time::timeout(Duration::from_secs(5), async {
+        while let Some(res) = set.join_next().await {
+            res.unwrap();
+        }
+    })
+    .await
+    .unwrap();
+
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(*actions.lock().unwrap(), vec![3, 1, 2]);
+}
+
+/// Test that replay collector rejects accelerations it cannot replay at.
+#[test]
+#[should_panic(expected = "replay acceleration must be finite and positive")]
+fn test_replay_collector_rejects_invalid_speed() {
+    ReplayCollector::<u64>::new("events.jsonl", ReplaySpeed::Accelerated(0.0));
+}
+
+/// Test that execution reports are published back to strategies as events.
+#[tokio::test]
+async fn test_engine_reports_executions() {
+    let executor = RecordingExecutor::default();
+    let actions = executor.actions.clone();
+
+    let mut engine: Engine<u64, u64> = Engine::new();
+    engine
+        .add_collector(Box::new(VecCollector { events: vec![1] }))
+        .unwrap();
+    engine
+        .add_strategy(Box::new(EchoStrategy::default()))
+        .unwrap();
+    engine.add_executor(Box::new(executor)).unwrap();
+    engine.report_executions(|report| {
+        assert!(report.result.is_ok());
+        (report.action < 1000).then(|| report.action + 1000)
+    });
+    let shutdown = engine.shutdown_handle();
+
+    let set = engine.run().await.unwrap();
+    wait_until(|| actions.lock().unwrap().len() == 2).await;
+    shut_down(shutdown, set).await;
+
+    assert_eq!(*actions.lock().unwrap(), vec![1, 1001]);
+}
+
+/// A strategy that echoes events, giving actions of 100 or more a deadline
+/// which has already passed.
+#[derive(Default)]
+struct ExpiringStrategy;
+
+#[async_trait]
+impl Strategy<u64, u64> for ExpiringStrategy {
+    async fn sync_state(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    async fn process_event(&mut self, event: u64) -> Result<Vec<u64>> {
+        Ok(vec![event])
+    }
+
+    fn action_deadline(&self, action: &u64) -> Option<Deadline> {
+        if *action >= 100 {
+            Some(Deadline::Instant(Instant::now()))
+        } else {
+            Some(Deadline::Block(U64::from(1)))
+        }
+    }
+}
+
+/// Test that executors drop actions whose deadline has passed.
+#[tokio::test]
+async fn test_engine_drops_expired_actions() {
+    let executor = RecordingExecutor::default();
+    let actions = executor.actions.clone();
+
+    let mut engine: Engine<u64, u64> = Engine::new();
+    engine
+        .add_collector(Box::new(VecCollector {
+            events: vec![1, 100, 2],
+        }))
+        .unwrap();
+    engine.add_strategy(Box::new(ExpiringStrategy)).unwrap();
+    engine.add_executor(Box::new(executor)).unwrap();
+    let shutdown = engine.shutdown_handle();
+
+    let set = engine.run().await.unwrap();
+    wait_until(|| actions.lock().unwrap().len() == 2).await;
+    shut_down(shutdown, set).await;
+
+    // Block deadlines are kept, since the executor does not know the chain head.
+    assert_eq!(*actions.lock().unwrap(), vec![1, 2]);
+    assert!(Deadline::Block(U64::from(5)).has_passed(Some(U64::from(5))));
+    assert!(!Deadline::Block(U64::from(5)).has_passed(Some(U64::from(4))));
+}
+
+/// Test that the engine records metrics for every component.
+#[tokio::test]
+async fn test_engine_records_metrics() {
+    let mut engine: Engine<u64, u64> = Engine::new();
+    engine
+        .add_collector(Box::new(VecCollector {
+            events: vec![1, 2, 3],
+        }))
+        .unwrap();
+    engine
+        .add_strategy(Box::new(FanOutStrategy::default()))
+        .unwrap();
+    engine
+        .add_executor(Box::new(RecordingExecutor::default()))
+        .unwrap();
+    let metrics = engine.metrics();
+    let shutdown = engine.shutdown_handle();
+
+    let set = engine.run().await.unwrap();
+    wait_until(|| {
+        let encoded = metrics.encode().unwrap();
+        encoded
+            .contains(r#"arbot_executions_total{executor="RecordingExecutor",outcome="success"} 9"#)
+    })
+    .await;
+    shut_down(shutdown, set).await;
+
+    let encoded = metrics.encode().unwrap();
+    assert!(encoded.contains(r#"arbot_events_received_total{collector="VecCollector"} 3"#));
+    assert!(encoded.contains(r#"arbot_events_processed_total{strategy="FanOutStrategy"} 3"#));
+    assert!(encoded.contains(r#"arbot_actions_emitted_total{strategy="FanOutStrategy"} 9"#));
+    assert!(encoded.contains(r#"arbot_process_event_seconds_count{strategy="FanOutStrategy"} 3"#));
+    assert!(encoded
+        .contains(r#"arbot_executions_total{executor="RecordingExecutor",outcome="success"} 9"#));
+    assert!(encoded.contains(r#"arbot_component_healthy{component="RecordingExecutor"} -1"#));
+}
+
+/// A strategy that only consumes even events, recording the ones it sees.
+#[derive(Default)]
+struct EvenStrategy {
+    processed: Arc<Mutex<Vec<u64>>>,
+}
+
+#[async_trait]
+impl Strategy<u64, u64> for EvenStrategy {
+    async fn sync_state(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    async fn process_event(&mut self, event: u64) -> Result<Vec<u64>> {
+        self.processed.lock().unwrap().push(event);
+        Ok(vec![])
+    }
+
+    fn event_filter(&self) -> Option<EventFilter<u64>> {
+        Some(Arc::new(|event| event % 2 == 0))
+    }
+}
+
+/// Test that strategies only receive the events their filter accepts.
+#[tokio::test]
+async fn test_engine_routes_events_to_strategies() {
+    let strategy = EvenStrategy::default();
+    let processed = strategy.processed.clone();
+    let executor = RecordingExecutor::default();
+    let actions = executor.actions.clone();
+
+    let mut engine: Engine<u64, u64> = Engine::new();
+    engine
+        .add_collector(Box::new(VecCollector {
+            events: vec![1, 2, 3, 4],
+        }))
+        .unwrap();
+    engine.add_strategy(Box::new(strategy)).unwrap();
+    engine
+        .add_strategy(Box::new(EchoStrategy::default()))
+        .unwrap();
+    engine.add_executor(Box::new(executor)).unwrap();
+    let shutdown = engine.shutdown_handle();
+
+    let set = engine.run().await.unwrap();
+    wait_until(|| processed.lock().unwrap().len() == 2 && actions.lock().unwrap().len() == 4).await;
+    shut_down(shutdown, set).await;
+
+    assert_eq!(*processed.lock().unwrap(), vec![2, 4]);
+    assert_eq!(*actions.lock().unwrap(), vec![1, 2, 3, 4]);
+}
+
+/// Test that two components with the same name are rejected.
+#[test]
+fn test_engine_rejects_duplicate_names() {
+    let mut engine: Engine<u64, u64> = Engine::new();
+    engine
+        .add_strategy(Box::new(EchoStrategy::default()))
+        .unwrap();
+    let error = engine
+        .add_strategy(Box::new(EchoStrategy::default()))
+        .unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "a component named EchoStrategy was already added to the engine"
+    );
+}
+
+/// Test that named maps tell components of the same type apart.
+#[test]
+fn test_engine_accepts_named_components() {
+    let mut engine: Engine<u64, u64> = Engine::new();
+    for name in ["first", "second"] {
+        let collector = CollectorMap::new(Box::new(VecCollector { events: vec![] }), |e| e);
+        engine
+            .add_collector(Box::new(collector.with_name(format!("{name} collector"))))
+            .unwrap();
+        let executor = ExecutorMap::new(Box::new(RecordingExecutor::default()), Some);
+        engine
+            .add_executor(Box::new(executor.with_name(format!("{name} executor"))))
+            .unwrap();
+    }
+    let collector = CollectorMap::new(Box::new(VecCollector { events: vec![] }), |e| e);
+    assert!(engine
+        .add_collector(Box::new(collector.with_name("first collector")))
+        .is_err());
+}
+
+/// A filtered strategy that resyncs when it lags.
+#[derive(Default)]
+struct FilteredLaggingStrategy {
+    syncs: Arc<AtomicU64>,
+}
+
+#[async_trait]
+impl Strategy<u64, u64> for FilteredLaggingStrategy {
+    async fn sync_state(&mut self) -> Result<()> {
+        self.syncs.fetch_add(1, Ordering::SeqCst);
+        Ok(())
+    }
+
+    async fn process_event(&mut self, _event: u64) -> Result<Vec<u64>> {
+        Ok(vec![])
+    }
+
+    fn event_filter(&self) -> Option<EventFilter<u64>> {
+        Some(Arc::new(|_| true))
+    }
+
+    fn on_lag(&mut self, _skipped: u64) -> LagPolicy {
+        LagPolicy::Resync
+    }
+}
+
+/// Test that events dropped by the router are reported as lag to routed
+/// strategies, which then resync.
+#[tokio::test]
+async fn test_engine_forwards_router_lag_to_filtered_strategies() {
+    let strategy = FilteredLaggingStrategy::default();
+    let syncs = strategy.syncs.clone();
+
+    let mut engine: Engine<u64, u64> = Engine::new().with_event_channel_capacity(4);
+    engine
+        .add_collector(Box::new(VecCollector {
+            events: (0..100).collect(),
+        }))
+        .unwrap();
+    engine.add_strategy(Box::new(strategy)).unwrap();
+    let lag_counters = engine.lag_counters();
+    let shutdown = engine.shutdown_handle();
+
+    let set = engine.run().await.unwrap();
+    wait_until(|| {
+        lag_counters.get("EventRouter") > 0
+            && lag_counters.get("FilteredLaggingStrategy") > 0
+            && syncs.load(Ordering::SeqCst) > 1
+    })
+    .await;
+    shut_down(shutdown, set).await;
+
+    assert!(lag_counters.get("EventRouter") > 0);
+    assert!(lag_counters.get("FilteredLaggingStrategy") > 0);
+    assert!(syncs.load(Ordering::SeqCst) > 1);
+}
+
+/// A strategy that processes events slowly, giving even events priority.
+#[derive(Default)]
+struct PriorityStrategy {
+    processed: Arc<Mutex<Vec<u64>>>,
+}
+
+#[async_trait]
+impl Strategy<u64, u64> for PriorityStrategy {
+    async fn sync_state(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    async fn process_event(&mut self, event: u64) -> Result<Vec<u64>> {
+        sleep(Duration::from_millis(20)).await;
+        self.processed.lock().unwrap().push(event);
+        Ok(vec![])
+    }
+
+    fn priority_events(&self) -> Option<EventFilter<u64>> {
+        Some(Arc::new(|event| event % 2 == 0))
+    }
+}
+
+/// Test that priority events are processed ahead of queued events.
+#[tokio::test]
+async fn test_engine_processes_priority_events_first() {
+    let strategy = PriorityStrategy::default();
+    let processed = strategy.processed.clone();
+
+    let mut engine: Engine<u64, u64> = Engine::new();
+    engine
+        .add_collector(Box::new(VecCollector {
+            events: vec![1, 3, 5, 2],
+        }))
+        .unwrap();
+    engine.add_strategy(Box::new(strategy)).unwrap();
+    let shutdown = engine.shutdown_handle();
+
+    let set = engine.run().await.unwrap();
+    wait_until(|| processed.lock().unwrap().len() == 4).await;
+    shut_down(shutdown, set).await;
+
+    let processed = processed.lock().unwrap().clone();
+    assert_eq!(processed.len(), 4);
+    let position = |event| processed.iter().position(|e| *e == event).unwrap();
+    assert!(position(2) < position(3));
+    assert!(position(2) < position(5));
+}
+
+/// An executor that takes longer for smaller actions, ordering odd and even
+/// actions separately.
+#[derive(Default)]
+struct KeyedExecutor {
+    completed: Arc<Mutex<Vec<u64>>>,
+}
+
+#[async_trait]
+impl Executor<u64> for KeyedExecutor {
+    async fn execute(&self, action: u64) -> Result<Option<Execution>> {
+        sleep(Duration::from_millis(60 - action * 10)).await;
+        self.completed.lock().unwrap().push(action);
+        Ok(Some(Execution::default()))
+    }
+
+    fn ordering_key(&self, action: &u64) -> Option<String> {
+        Some((action % 2).to_string())
+    }
+}
+
+/// Test that executors run actions concurrently, except those sharing an
+/// ordering key.
+#[tokio::test]
+async fn test_engine_executes_actions_concurrently() {
+    let executor = KeyedExecutor::default();
+    let completed = executor.completed.clone();
+
+    let mut engine: Engine<u64, u64> = Engine::new();
+    engine
+        .add_collector(Box::new(VecCollector {
+            events: vec![1, 2, 3, 5],
+        }))
+        .unwrap();
+    engine
+        .add_strategy(Box::new(EchoStrategy::default()))
+        .unwrap();
+    engine
+        .add_executor_with_concurrency(Box::new(executor), 4)
+        .unwrap();
+    let shutdown = engine.shutdown_handle();
+
+    let set = engine.run().await.unwrap();
+    wait_until(|| completed.lock().unwrap().len() == 4).await;
+    shut_down(shutdown, set).await;
+
+    // 2 overtakes 1, while odd actions complete in order despite shorter sleeps.
+    assert_eq!(*completed.lock().unwrap(), vec![2, 1, 3, 5]);
+}
+
+/// An executor ordering all actions under one key, releasing the key before
+/// waiting for each action to complete.
+#[derive(Default)]
+struct ReleasingExecutor {
+    sent: Arc<Mutex<Vec<u64>>>,
+    completed: Arc<Mutex<Vec<u64>>>,
+}
+
+#[async_trait]
+impl Executor<u64> for ReleasingExecutor {
+    async fn execute(&self, action: u64) -> Result<Option<Execution>> {
+        self.sent.lock().unwrap().push(action);
+        release_ordering();
+        sleep(Duration::from_millis(100)).await;
+        self.completed.lock().unwrap().push(action);
+        Ok(Some(Execution::default()))
+    }
+
+    fn ordering_key(&self, _action: &u64) -> Option<String> {
+        Some("sender".to_string())
+    }
+}
+
+/// Test that an action releasing its ordering key lets the next action with
+/// the same key start before it completes.
+#[tokio::test]
+async fn test_engine_releases_ordering_keys() {
+    let executor = ReleasingExecutor::default();
+    let sent = executor.sent.clone();
+    let completed = executor.completed.clone();
+
+    let mut engine: Engine<u64, u64> = Engine::new();
+    engine
+        .add_collector(Box::new(VecCollector {
+            events: vec![1, 2, 3],
+        }))
+        .unwrap();
+    engine
+        .add_strategy(Box::new(EchoStrategy::default()))
+        .unwrap();
+    engine
+        .add_executor_with_concurrency(Box::new(executor), 4)
+        .unwrap();
+    let shutdown = engine.shutdown_handle();
+
+    let set = engine.run().await.unwrap();
+    wait_until(|| sent.lock().unwrap().len() == 3).await;
+    assert!(completed.lock().unwrap().is_empty());
+    assert_eq!(*sent.lock().unwrap(), vec![1, 2, 3]);
+    wait_until(|| completed.lock().unwrap().len() == 3).await;
+    shut_down(shutdown, set).await;
+}
+
+/// A collector that emits the events sent through its channel.
+struct ChannelCollector {
+    receiver: Mutex<Option<tokio::sync::mpsc::UnboundedReceiver<u64>>>,
+}
+
+#[async_trait]
+impl Collector<u64> for ChannelCollector {
+    async fn get_event_stream(&self) -> Result<CollectorStream<u64>> {
+        let receiver = self.receiver.lock().unwrap().take().unwrap();
+        Ok(Box::pin(
+            tokio_stream::wrappers::UnboundedReceiverStream::new(receiver),
+        ))
+    }
+}
+
+/// A strategy that echoes events and reports how often it synced.
+#[derive(Default)]
+struct InspectableStrategy {
+    syncs: Arc<AtomicU64>,
+}
+
+#[async_trait]
+impl Strategy<u64, u64> for InspectableStrategy {
+    async fn sync_state(&mut self) -> Result<()> {
+        self.syncs.fetch_add(1, Ordering::SeqCst);
+        Ok(())
+    }
+
+    async fn process_event(&mut self, event: u64) -> Result<Vec<u64>> {
+        Ok(vec![event])
+    }
+
+    fn state_summary(&self) -> serde_json::Value {
+        serde_json::json!({ "syncs": self.syncs.load(Ordering::SeqCst) })
+    }
+}
+
+/// Test that components can be paused, resumed, resynced and inspected.
+#[tokio::test]
+async fn test_engine_control_plane() {
+    let (events, receiver) = tokio::sync::mpsc::unbounded_channel();
+    let strategy = InspectableStrategy::default();
+    let syncs = strategy.syncs.clone();
+    let executor = RecordingExecutor::default();
+    let actions = executor.actions.clone();
+
+    let mut engine: Engine<u64, u64> = Engine::new();
+    engine
+        .add_collector(Box::new(ChannelCollector {
+            receiver: Mutex::new(Some(receiver)),
+        }))
+        .unwrap();
+    engine.add_strategy(Box::new(strategy)).unwrap();
+    engine.add_executor(Box::new(executor)).unwrap();
+    let control = engine.control();
+    let metrics = engine.metrics();
+    let shutdown = engine.shutdown_handle();
+
+    let set = engine.run().await.unwrap();
+    wait_until(|| {
+        control
+            .components()
+            .iter()
+            .all(|c| c.health == Some(Health::Healthy))
+    })
+    .await;
+
+    let components = control.components();
+    let names: Vec<_> = components.iter().map(|c| c.name.as_str()).collect();
+    assert_eq!(names.len(), 3);
+    assert!(names.contains(&"ChannelCollector"));
+    assert!(components.iter().all(|c| c.health == Some(Health::Healthy)));
+
+    // Actions are held back while the executor is paused.
+    control.pause("RecordingExecutor").unwrap();
+    events.send(1).unwrap();
+    wait_until(|| {
+        let encoded = metrics.encode().unwrap();
+        encoded.contains(r#"arbot_actions_emitted_total{strategy="InspectableStrategy"} 1"#)
+    })
+    .await;
+    assert!(actions.lock().unwrap().is_empty());
+    assert!(control
+        .components()
+        .iter()
+        .any(|c| c.name == "RecordingExecutor" && c.paused));
+    control.resume("RecordingExecutor").unwrap();
+    wait_until(|| !actions.lock().unwrap().is_empty()).await;
+    assert_eq!(*actions.lock().unwrap(), vec![1]);
+
+    control.resync("InspectableStrategy").await.unwrap();
+    wait_until(|| syncs.load(Ordering::SeqCst) == 2).await;
+    let summary = control.state_summary("InspectableStrategy").await.unwrap();
+    assert_eq!(summary, serde_json::json!({ "syncs": 2 }));
+
+    assert!(control.pause("ChannelCollector").is_err());
+    assert!(control.resync("Unknown").await.is_err());
+
+    shut_down(shutdown, set).await;
+}
+
+/// Test that a paused strategy drains its queue and stops on shutdown.
+#[tokio::test]
+async fn test_engine_shuts_down_paused_strategies() {
+    let (events, receiver) = tokio::sync::mpsc::unbounded_channel();
+    let executor = RecordingExecutor::default();
+    let actions = executor.actions.clone();
+
+    let mut engine: Engine<u64, u64> = Engine::new();
+    engine
+        .add_collector(Box::new(ChannelCollector {
+            receiver: Mutex::new(Some(receiver)),
+        }))
+        .unwrap();
+    engine
+        .add_strategy(Box::new(InspectableStrategy::default()))
+        .unwrap();
+    engine.add_executor(Box::new(executor)).unwrap();
+    let control = engine.control();
+    let shutdown = engine.shutdown_handle();
+
+    let set = engine.run().await.unwrap();
+    wait_until(|| {
+        control
+            .components()
+            .iter()
+            .all(|c| c.health == Some(Health::Healthy))
+    })
+    .await;
+    control.pause("InspectableStrategy").unwrap();
+    events.send(1).unwrap();
+    sleep(Duration::from_millis(50)).await;
+    assert!(actions.lock().unwrap().is_empty());
+
+    shut_down(shutdown, set).await;
+    assert_eq!(*actions.lock().unwrap(), vec![1]);
+}
+
+/// A strategy that sums the events it processes, persisting the sum in its
+/// snapshots.
+#[derive(Default)]
+struct SummingStrategy {
+    sum: Arc<AtomicU64>,
+    sum_at_sync: Arc<AtomicU64>,
+}
+
+#[async_trait]
+impl Strategy<u64, u64> for SummingStrategy {
+    async fn sync_state(&mut self) -> Result<()> {
+        self.sum_at_sync
+            .store(self.sum.load(Ordering::SeqCst), Ordering::SeqCst);
+        Ok(())
+    }
+
+    async fn process_event(&mut self, event: u64) -> Result<Vec<u64>> {
+        self.sum.fetch_add(event, Ordering::SeqCst);
+        Ok(vec![])
+    }
+
+    fn snapshot(&self) -> Result<Option<serde_json::Value>> {
+        Ok(Some(serde_json::json!(self.sum.load(Ordering::SeqCst))))
+    }
+
+    fn restore(&mut self, snapshot: serde_json::Value) -> Result<()> {
+        self.sum
+            .store(serde_json::from_value(snapshot)?, Ordering::SeqCst);
+        Ok(())
+    }
+}
+
+/// Test that strategies are restored from the snapshot taken when they last stopped.
+#[tokio::test]
+async fn test_engine_snapshots_and_restores_strategies() {
+    let dir = std::env::temp_dir().join(format!("arbot-snapshots-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+
+    let mut sums_at_sync = vec![];
+    for round in 1..=2 {
+        let strategy = SummingStrategy::default();
+        let sum = strategy.sum.clone();
+        let sum_at_sync = strategy.sum_at_sync.clone();
+        let mut engine: Engine<u64, u64> = Engine::new().with_snapshots(SnapshotStore::new(&dir));
+        engine
+            .add_collector(Box::new(VecCollector {
+                events: vec![1, 2, 3],
+            }))
+            .unwrap();
+        engine.add_strategy(Box::new(strategy)).unwrap();
+        let shutdown = engine.shutdown_handle();
+
+        let set = engine.run().await.unwrap();
+        wait_until(|| sum.load(Ordering::SeqCst) == 6 * round).await;
+        shut_down(shutdown, set).await;
+        sums_at_sync.push(sum_at_sync.load(Ordering::SeqCst));
+    }
+
+    assert_eq!(sums_at_sync, vec![0, 6]);
+    let saved = SnapshotStore::new(&dir)
+        .load("SummingStrategy")
+        .await
+        .unwrap();
+    assert_eq!(saved, Some(serde_json::json!(12)));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+/// Test that interval collector emits ticks on a fixed schedule.
+#[tokio::test]
+async fn test_interval_collector_sends_ticks() {
+    let collector = IntervalCollector::every(Duration::from_millis(20));
+    let started = Instant::now();
+    let ticks: Vec<Tick> = collector
+        .get_event_stream()
+        .await
+        .unwrap()
+        .take(3)
+        .collect()
+        .await;
+    assert!(started.elapsed() >= Duration::from_millis(60));
+    assert_eq!(
+        ticks.iter().map(|tick| tick.sequence).collect::<Vec<_>>(),
+        vec![0, 1, 2]
+    );
+    // Ticks are stamped with their deadline, not the time they were emitted.
+    for pair in ticks.windows(2) {
+        assert_eq!(
+            pair[1]
+                .scheduled_at
+                .duration_since(pair[0].scheduled_at)
+                .unwrap(),
+            Duration::from_millis(20)
+        );
+    }
+}
+
+/// Test that interval collector aligns ticks to the wall clock.
+#[tokio::test]
+async fn test_interval_collector_aligns_ticks() {
+    let period = Duration::from_millis(50);
+    let collector = IntervalCollector::aligned(period);
+    let ticks: Vec<Tick> = collector
+        .get_event_stream()
+        .await
+        .unwrap()
+        .take(2)
+        .collect()
+        .await;
+    for tick in &ticks {
+        let since_epoch = tick
+            .scheduled_at
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap();
+        assert_eq!(since_epoch.as_nanos() % period.as_nanos(), 0);
+    }
+    assert_eq!(
+        ticks[1]
+            .scheduled_at
+            .duration_since(ticks[0].scheduled_at)
+            .unwrap(),
+        period
+    );
+}
+
+/// How a connection to the OpenSea stand-in behaves.
+#[derive(Clone, Copy)]
+enum StandIn {
+    /// Replies to joins and heartbeats.
+    Healthy,
+    /// Drops the socket once a channel is joined.
+    DropAfterJoin,
+    /// Replies to joins but never to heartbeats.
+    IgnoreHeartbeats,
+    /// Pushes a `ping` event on the joined channel before replying to a join.
+    PushBeforeReply,
+}
+
+/// Spawns a local stand-in for the OpenSea stream, speaking the Phoenix
+/// protocol. Connections behave as listed, then healthily. Returns the
+/// websocket endpoint, and the topics joined so far.
+async fn spawn_opensea_stand_in(connections: Vec<StandIn>) -> (String, Arc<Mutex<Vec<String>>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("ws://{}/socket/websocket", listener.local_addr().unwrap());
+    let joined = Arc::new(Mutex::new(vec![]));
+    let topics = joined.clone();
+    tokio::spawn(async move {
+        for behaviour in connections
+            .into_iter()
+            .chain(std::iter::repeat(StandIn::Healthy))
+        {
+            let (stream, _) = listener.accept().await.unwrap();
+            tokio::spawn(serve_opensea_stand_in(stream, behaviour, topics.clone()));
+        }
+    });
+    (url, joined)
+}
+
+async fn serve_opensea_stand_in(
+    stream: TcpStream,
+    behaviour: StandIn,
+    joined: Arc<Mutex<Vec<String>>>,
+) {
+    let mut socket = tokio_tungstenite::accept_async(stream).await.unwrap();
+    while let Some(Ok(Message::Text(text))) = socket.next().await {
+        let message: Value = serde_json::from_str(&text).unwrap();
+        let heartbeat = message["event"] == "heartbeat";
+        if heartbeat && matches!(behaviour, StandIn::IgnoreHeartbeats) {
+            continue;
+        }
+        if message["event"] == "phx_join" {
+            joined
+                .lock()
+                .unwrap()
+                .push(message["topic"].as_str().unwrap().to_string());
+            if matches!(behaviour, StandIn::PushBeforeReply) {
+                let push = json!({ "topic": message["topic"], "event": "ping", "payload": {}, "ref": null });
+                socket.send(Message::Text(push.to_string())).await.unwrap();
+            }
+        }
+        let reply = json!({
+            "topic": message["topic"],
+            "event": "phx_reply",
+            "payload": { "status": "ok", "response": {} },
+            "ref": message["ref"],
+        });
+        socket.send(Message::Text(reply.to_string())).await.unwrap();
+        if !heartbeat && matches!(behaviour, StandIn::DropAfterJoin) {
+            return;
+        }
+    }
+}
+
+/// Test that opensea collector resubscribes after the socket drops, and reports the gap.
+#[tokio::test]
+async fn test_opensea_collector_reconnects_after_drop() {
+    let (url, _) = spawn_opensea_stand_in(vec![StandIn::DropAfterJoin]).await;
+    let collector = OpenseaOrderCollector::new("key".to_string())
+        .with_url(url)
+        .with_reconnect_delay(Duration::from_millis(10));
+    let mut stream: CollectorStream<OpenseaEvent> = collector.get_event_stream().await.unwrap();
+
+    let event = timeout(Duration::from_secs(5), stream.next())
+        .await
+        .unwrap()
+        .unwrap();
+    match event {
+        OpenseaEvent::StreamGap(gap) => assert!(gap.from <= gap.to),
+        event => panic!("unexpected event {:?}", event),
+    }
+}
+
+/// Test that opensea collector detects a dead socket through heartbeats.
+#[tokio::test]
+async fn test_opensea_collector_detects_missed_heartbeats() {
+    let (url, _) = spawn_opensea_stand_in(vec![StandIn::IgnoreHeartbeats]).await;
+    let collector = OpenseaOrderCollector::new("key".to_string())
+        .with_url(url)
+        .with_heartbeat_interval(Duration::from_millis(50))
+        .with_reconnect_delay(Duration::from_millis(10));
+    let mut stream: CollectorStream<OpenseaEvent> = collector.get_event_stream().await.unwrap();
+
+    let started = Instant::now();
+    let event = timeout(Duration::from_secs(5), stream.next())
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(matches!(event, OpenseaEvent::StreamGap(_)));
+    // the first heartbeat goes unanswered, and the socket is dropped when the second is due
+    assert!(started.elapsed() >= Duration::from_millis(100));
+
+    // the healthy connection keeps answering, so no further gap is reported
+    assert!(timeout(Duration::from_millis(300), stream.next())
+        .await
+        .is_err());
+}
+
+/// Test that the Phoenix socket keeps the messages it receives while joining.
+#[tokio::test]
+async fn test_phoenix_socket_keeps_messages_received_while_joining() {
+    let (url, _) = spawn_opensea_stand_in(vec![StandIn::PushBeforeReply]).await;
+    let mut socket = PhoenixSocket::connect(&url).await.unwrap();
+    socket.join("collection:first").await.unwrap();
+    socket.join("collection:second").await.unwrap();
+
+    for topic in ["collection:first", "collection:second"] {
+        let message = socket.next_message().await.unwrap().unwrap();
+        assert_eq!(
+            (message.topic.as_str(), message.event.as_str()),
+            (topic, "ping")
+        );
+    }
+}
+
+/// Test that opensea collector joins the channels of the configured collections.
+#[tokio::test]
+async fn test_opensea_collector_joins_configured_collections() {
+    let (url, joined) = spawn_opensea_stand_in(vec![]).await;
+    let collector = OpenseaOrderCollector::new("key".to_string())
+        .with_url(url)
+        .with_collections(["first", "second"]);
+    let _stream: CollectorStream<OpenseaEvent> = collector.get_event_stream().await.unwrap();
+    assert_eq!(
+        *joined.lock().unwrap(),
+        vec!["collection:first", "collection:second"]
+    );
+
+    let collector = OpenseaOrderCollector::new("key".to_string());
+    assert_eq!(collector.topics(), vec!["collection:*"]);
+}
+
+/// Test that opensea collector connects to the configured network with its API key.
+#[test]
+fn test_opensea_collector_selects_network_endpoint() {
+    let collector = OpenseaOrderCollector::new("key".to_string());
+    let endpoint = collector.endpoint().unwrap();
+    assert_eq!(endpoint.host_str(), Some("stream.openseabeta.com"));
+    assert_eq!(endpoint.query(), Some("token=key"));
+
+    let collector = OpenseaOrderCollector::new("a&b".to_string()).with_network(Network::Testnet);
+    let endpoint = collector.endpoint().unwrap();
+    assert_eq!(endpoint.host_str(), Some("testnets-stream.openseabeta.com"));
+    assert_eq!(endpoint.query(), Some("token=a%26b"));
+
+    // query parameters of a custom endpoint are kept
+    let collector = OpenseaOrderCollector::new("key".to_string())
+        .with_url("ws://127.0.0.1:4000/socket/websocket?vsn=2.0.0");
+    assert_eq!(
+        collector.endpoint().unwrap().query(),
+        Some("vsn=2.0.0&token=key")
+    );
+}
+
+/// Test that every opensea event kind maps to its stream event name.
+#[test]
+fn test_opensea_event_kinds_match_event_names() {
+    for kind in OpenseaEventKind::ALL {
+        assert_eq!(
+            OpenseaEventKind::from_event_name(kind.event_name()),
+            Some(kind)
+        );
+    }
+    assert_eq!(
+        OpenseaEventKind::from_event_name("item_metadata_updated"),
+        None
+    );
+}
+
+/// A collector that emits its events one after the other, `delay` apart.
+struct DelayedCollector {
+    events: Vec<u64>,
+    delay: Duration,
+}
+
+#[async_trait]
+impl Collector<u64> for DelayedCollector {
+    async fn get_event_stream(&self) -> Result<CollectorStream<u64>> {
+        let delay = self.delay;
+        let stream = futures::stream::iter(self.events.clone())
+            .then(move |event| async move {
+                sleep(delay).await;
+                event
+            })
+            .chain(futures::stream::pending());
+        Ok(Box::pin(stream))
+    }
+}
+
+/// A collector whose provider is down, counting subscription attempts.
+#[derive(Default)]
+struct DownCollector {
+    attempts: Arc<AtomicU64>,
+}
+
+#[async_trait]
+impl Collector<u64> for DownCollector {
+    async fn get_event_stream(&self) -> Result<CollectorStream<u64>> {
+        self.attempts.fetch_add(1, Ordering::SeqCst);
+        anyhow::bail!("connection refused")
+    }
+}
+
+/// Test that racing collector forwards the first copy of each event and records provider latency.
+#[tokio::test]
+async fn test_racing_collector_deduplicates_providers() {
+    let collector = RacingCollector::new(|event: &u64| *event)
+        .with_provider(
+            "fast",
+            Box::new(DelayedCollector {
+                events: vec![1, 2, 3],
+                delay: Duration::from_millis(10),
+            }),
+        )
+        .with_provider(
+            "slow",
+            Box::new(DelayedCollector {
+                events: vec![1, 2, 3, 4],
+                delay: Duration::from_millis(60),
+            }),
+        )
+        .with_provider("down", Box::new(DownCollector::default()))
+        .with_resubscribe_delay(Duration::from_millis(10));
+    let latency = collector.latency();
+    let stream = collector.get_event_stream().await.unwrap();
+
+    let events: Vec<u64> = timeout(Duration::from_secs(5), stream.take(4).collect())
+        .await
+        .unwrap();
+    assert_eq!(events, vec![1, 2, 3, 4]);
+
+    let fast = latency.get("fast");
+    assert_eq!((fast.delivered, fast.first), (3, 3));
+    let slow = latency.get("slow");
+    assert_eq!((slow.delivered, slow.first), (4, 1));
+    assert!(slow.max_delay >= Duration::from_millis(50));
+    assert!(slow.mean_delay() <= slow.max_delay);
+    assert_eq!(latency.get("down").delivered, 0);
+}
+
+/// Test that racing collector forwards a copy again once its key left the
+/// de-duplication window.
+#[tokio::test]
+async fn test_racing_collector_forgets_keys_after_window() {
+    let collector = RacingCollector::new(|event: &u64| *event)
+        .with_provider(
+            "echo",
+            Box::new(DelayedCollector {
+                events: vec![1, 1, 2, 1],
+                delay: Duration::from_millis(50),
+            }),
+        )
+        .with_dedup_window(Duration::from_millis(75));
+    let stream = collector.get_event_stream().await.unwrap();
+
+    let events: Vec<u64> = timeout(Duration::from_secs(5), stream.take(3).collect())
+        .await
+        .unwrap();
+    assert_eq!(events, vec![1, 2, 1]);
+}
+
+/// Test that racing collector backs off exponentially when resubscribing to
+/// a provider that stays down.
+#[tokio::test]
+async fn test_racing_collector_backs_off_resubscriptions() {
+    let down = DownCollector::default();
+    let attempts = down.attempts.clone();
+    let collector = RacingCollector::new(|event: &u64| *event)
+        .with_provider(
+            "idle",
+            Box::new(DelayedCollector {
+                events: vec![],
+                delay: Duration::ZERO,
+            }),
+        )
+        .with_provider("down", Box::new(down))
+        .with_resubscribe_delay(Duration::from_millis(10))
+        .with_max_resubscribe_delay(Duration::from_millis(40));
+    let mut stream = collector.get_event_stream().await.unwrap();
+
+    // Retrying every 10ms would take 30 attempts; backing off takes about 9.
+    assert!(timeout(Duration::from_millis(300), stream.next())
+        .await
+        .is_err());
+    let attempts = attempts.load(Ordering::SeqCst);
+    assert!((3..=12).contains(&attempts), "{} attempts", attempts);
+}
+
+/// Test that racing collector fails when no provider can be subscribed to.
+#[tokio::test]
+async fn test_racing_collector_fails_without_providers() {
+    let collector = RacingCollector::new(|event: &u64| *event)
+        .with_provider("down", Box::new(DownCollector::default()));
+    assert!(collector.get_event_stream().await.is_err());
+}
@@ -29,7 +29,10 @@ impl OpenSeaV2Client {
         let mut builder = ClientBuilder::new();
 
         let mut headers = HeaderMap::new();
-        headers.insert("X-API-KEY", header::HeaderValue::from_str(&cfg.api_key).unwrap());
+        headers.insert(
+            "X-API-KEY",
+            header::HeaderValue::from_str(&cfg.api_key).unwrap(),
+        );
         builder = builder.default_headers(headers);
         let client = builder.build().unwrap();
 
@@ -38,7 +41,10 @@ impl OpenSeaV2Client {
 
     /// Call the fulfill listing endpoint, which returns the arguments necessary
     /// to fulfill an order onchain.
-    pub async fn fulfill_listing(&self, req: FulfillListingRequest) -> Result<FulfillListingResponse, OpenSeaApiError> {
+    pub async fn fulfill_listing(
+        &self,
+        req: FulfillListingRequest,
+    ) -> Result<FulfillListingResponse, OpenSeaApiError> {
         // let res = self
         //     .client
         //     .post(FULFILL_LISTING_URL)
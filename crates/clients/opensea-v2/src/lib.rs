@@ -7,7 +7,7 @@
 pub mod client;
 
 /// This module contains constants used by the client.
-mod constants;
+pub mod constants;
 
 /// This module contains the core type definitions for the client.
 pub mod types;
@@ -21,3 +21,9 @@ pub static POOL_EVENT_SIGNATURES: Lazy<Vec<TxHash>> = Lazy::new(|| {
         bindings::lssvm_pair::TokenWithdrawalFilter::signature(),
     ]
 });
+
+/// 池子连续失败多少次后暂停使用
+pub const MAX_POOL_FAILURES: u32 = 3;
+
+/// 池子暂停使用的区块数
+pub const POOL_BACKOFF_BLOCKS: u64 = 50;
@@ -1,4 +1,4 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
 use std::sync::Arc;
 
@@ -7,21 +7,22 @@ use async_trait::async_trait;
 use bindings::lssvm_pair_factory::{LSSVMPairFactory, NewPairFilter};
 use bindings::sudo_opensea_arb::SudoOpenseaArb;
 use bindings::sudo_pair_quoter::{SellQuote, SudoPairQuoter, SUDOPAIRQUOTER_DEPLOYED_BYTECODE};
-use tracing::info;
+use tracing::{info, warn};
 
-use crate::constants::FACTORY_DEPLOYMENT_BLOCK;
-use crate::types::Config;
-use anyhow::Result;
-use arbot_core::collectors::block_collector::NewBlock;
-use arbot_core::collectors::opensea_order_collector::OpenseaOrder;
+use crate::constants::{FACTORY_DEPLOYMENT_BLOCK, MAX_POOL_FAILURES, POOL_BACKOFF_BLOCKS};
+use crate::types::{ArbTx, Config, PoolSnapshot};
+use anyhow::{Context, Result};
+use arbot_core::collectors::block_collector::{NewBlock, Reorg};
+use arbot_core::collectors::opensea_order_collector::{OpenseaEvent, OpenseaOrder, StreamGap};
 use arbot_core::executors::mempool_executor::{GasBidInfo, SubmitTxToMempool};
-use arbot_core::types::Strategy;
+use arbot_core::types::{Deadline, EventFilter, ExecutionReport, LagPolicy, Strategy};
 use arbot_core::utilities::state_override_middleware::StateOverrideMiddleware;
 use ethers::providers::Middleware;
 use ethers::types::{Filter, H256};
-use ethers::types::{H160, U256};
+use ethers::types::{H160, U256, U64};
 
 use opensea_stream::schema::Chain;
+use serde_json::{json, Value};
 use opensea_v2::client::OpenSeaV2Client;
 
 use super::constants::{LSSVM_PAIR_FACTORY_ADDRESS, POOL_EVENT_SIGNATURES};
@@ -57,6 +58,24 @@ pub struct OpenseaSudoArb<M> {
 
     /// 出价的利润数量
     bid_percentage: u64,
+
+    /// 回放记录的事件, 处理早于已同步区块的区块
+    replay: bool,
+
+    /// 已经成功套利的订单, 不再重复提交
+    filled_orders: HashSet<H256>,
+
+    /// Map Sudo pool 地址到连续失败的次数
+    pool_failures: HashMap<H160, u32>,
+
+    /// Map Sudo pool 地址到暂停使用直到的区块
+    pool_backoff_until: HashMap<H160, u64>,
+
+    /// 最后同步或处理的区块
+    last_block: u64,
+
+    /// opensea 最近一次断流, 期间的挂单可能已错过
+    last_stream_gap: Option<StreamGap>,
 }
 
 impl<M: Middleware + 'static> OpenseaSudoArb<M> {
@@ -93,6 +112,12 @@ impl<M: Middleware + 'static> OpenseaSudoArb<M> {
             sudo_pools: HashMap::new(),
             pool_bids: HashMap::new(),
             bid_percentage: config.bid_percentage,
+            replay: config.replay,
+            filled_orders: HashSet::new(),
+            pool_failures: HashMap::new(),
+            pool_backoff_until: HashMap::new(),
+            last_block: 0,
+            last_stream_gap: None,
         }
     }
 
@@ -105,20 +130,24 @@ impl<M: Middleware + 'static> Strategy<Event, Action> for OpenseaSudoArb<M> {
     // In order to sync this strategy, we need to get the current bid for all Sudo pools.
     async fn sync_state(&mut self) -> Result<()> {
 
-        // Block in which the pool factory was deployed.
-        let start_block = FACTORY_DEPLOYMENT_BLOCK;                                 // pool factory 部署的区块
+        // Catch up from the last synced block if there is one, for example after
+        // restoring a snapshot; otherwise start from the block in which the pool
+        // factory was deployed.
+        let (start_block, mut pool_addresses) = if self.last_block > 0 {           // 从上次同步的区块继续
+            (self.last_block + 1, self.pool_bids.keys().copied().collect::<Vec<_>>())
+        } else {
+            (FACTORY_DEPLOYMENT_BLOCK, vec![])                                      // pool factory 部署的区块
+        };
 
         let current_block = self.client.get_block_number().await?.as_u64();         // 当前区块
 
         // Get all Sudo pool addresses deployed in the block range. 
-        let pool_addresses = self.get_new_pools(start_block, current_block).await?; // 获取在区块范围内 部署的所有 Sudo pool 地址
-        info!("found {} deployed sudo pools", pool_addresses.len());                // 打印日志
+        let new_pools = self.get_new_pools(start_block, current_block).await?;      // 获取在区块范围内 部署的所有 Sudo pool 地址
+        info!("found {} deployed sudo pools", new_pools.len());                     // 打印日志
+        pool_addresses.extend(new_pools);
 
-        // Get current bids for update state for all Sudo pools.
-        for addresses in pool_addresses.chunks(200) {                               // 每次处理 200 个 Sudo pool 地址
-            let quotes = self.get_quotes_for_pools(addresses.to_vec()).await?;      // 获取这些 Sudo pool 的报价
-            self.update_internal_pool_state(quotes);                                // 更新内部 Sudo pool 状态
-        }
+        self.replace_pool_state(&pool_addresses).await?;                            // 更新内部 Sudo pool 状态
+        self.last_block = current_block;
         info!(
             "done syncing state, found available pools for {} collections",         // 打印日志
             self.sudo_pools.len()
@@ -128,17 +157,91 @@ impl<M: Middleware + 'static> Strategy<Event, Action> for OpenseaSudoArb<M> {
     }
 
     // 处理传入的事件, 看看我们是否可以 arb 新的订单, 并在新的区块上更新内部状态
-    async fn process_event(&mut self, event: Event) -> Option<Action> {
+    // 如果区块处理失败, 策略将不同步, 引擎会重新同步状态
+    async fn process_event(&mut self, event: Event) -> Result<Vec<Action>> {
         match event {
-            Event::OpenseaOrder(order) => self.process_order_event(*order).await,
-            Event::NewBlock(block) => match self.process_new_block_event(block).await {
-                Ok(_) => None,
-                Err(e) => {
-                    panic!("Strategy is out of sync {}", e);
-                }
-            },
+            Event::Opensea(OpenseaEvent::Listed(order)) => Ok(self.process_order_event(*order).await.into_iter().collect()),
+            Event::Opensea(OpenseaEvent::StreamGap(gap)) => {
+                self.process_stream_gap(gap);
+                Ok(vec![])
+            }
+            // Only listings are traded.
+            Event::Opensea(_) => Ok(vec![]),
+            Event::NewBlock(block) => {
+                self.process_new_block_event(*block)
+                    .await
+                    .context("strategy is out of sync")?;
+                Ok(vec![])
+            }
+            Event::Reorg(reorg) => {
+                self.process_reorg_event(reorg)
+                    .await
+                    .context("strategy is out of sync")?;
+                Ok(vec![])
+            }
+            Event::ExecutionReport(report) => {
+                self.process_execution_report(*report);
+                Ok(vec![])
+            }
         }
     }
+
+    // 套利交易只对下一个区块有效, 之后池子的出价可能已经改变
+    // An arb is computed against the latest block, so it is only valid for the next one.
+    fn action_deadline(&self, _action: &Action) -> Option<Deadline> {
+        (self.last_block > 0).then(|| Deadline::Block(U64::from(self.last_block + 1)))
+    }
+
+    // 区块, 链重组和执行结果会更新池子状态, 因此优先于订单处理
+    // Blocks, reorgs and execution reports update pool state, so process them before queued orders.
+    fn priority_events(&self) -> Option<EventFilter<Event>> {
+        Some(Arc::new(|event| matches!(event, Event::NewBlock(_) | Event::Reorg(_) | Event::ExecutionReport(_))))
+    }
+
+    // 保存池子状态, 重启后只需从上次同步的区块继续
+    // Persist pool state, so that a restart only catches up from the last synced block.
+    fn snapshot(&self) -> Result<Option<Value>> {
+        let snapshot = PoolSnapshot {
+            sudo_pools: self.sudo_pools.clone(),
+            pool_bids: self.pool_bids.clone(),
+            last_block: self.last_block,
+        };
+        Ok(Some(serde_json::to_value(snapshot)?))
+    }
+
+    fn restore(&mut self, snapshot: Value) -> Result<()> {
+        let snapshot: PoolSnapshot = serde_json::from_value(snapshot)?;
+        info!("restored {} pools synced up to block {}", snapshot.pool_bids.len(), snapshot.last_block);
+        self.sudo_pools = snapshot.sudo_pools;
+        self.pool_bids = snapshot.pool_bids;
+        self.last_block = snapshot.last_block;
+        Ok(())
+    }
+
+    // 当前追踪的池子和订单状态, 供控制面查看
+    // Summary of tracked pools and orders, served by the control plane.
+    fn state_summary(&self) -> Value {
+        let pools_per_collection: HashMap<String, usize> = self
+            .sudo_pools
+            .iter()
+            .map(|(nft_address, pools)| (format!("{:?}", nft_address), pools.len()))
+            .collect();
+        json!({
+            "last_block": self.last_block,
+            "collections": self.sudo_pools.len(),
+            "pools": self.pool_bids.len(),
+            "pools_per_collection": pools_per_collection,
+            "filled_orders": self.filled_orders.len(),
+            "backed_off_pools": self.pool_backoff_until.values().filter(|until| **until > self.last_block).count(),
+            "last_stream_gap": self.last_stream_gap,
+        })
+    }
+
+    // 错过的区块意味着 pool 状态已过期, 因此需要重新同步
+    // A missed block means pool state is stale, so resync before continuing.
+    fn on_lag(&mut self, _skipped: u64) -> LagPolicy {
+        LagPolicy::Resync
+    }
 }
 
 impl<M: Middleware + 'static> OpenseaSudoArb<M> {
@@ -157,10 +260,16 @@ impl<M: Middleware + 'static> OpenseaSudoArb<M> {
             return None;
         }
 
-        // Find pool with highest bid.
+        // Ignore orders we already filled.
+        if self.filled_orders.contains(&event.listing.order_hash) {
+            return None;
+        }
+
+        // Find pool with highest bid, skipping pools that keep failing.
         let pools = self.sudo_pools.get(&nft_address)?;
         let (max_pool, max_bid) = pools
             .iter()
+            .filter(|pool| !self.is_backed_off(pool))
             .filter_map(|pool| self.pool_bids.get(pool).map(|bid| (pool, bid)))
             .max_by(|a, b| a.1.cmp(b.1))?;
 
@@ -176,6 +285,13 @@ impl<M: Middleware + 'static> OpenseaSudoArb<M> {
 
     /// Process new block events, updating the internal state.
     async fn process_new_block_event(&mut self, event: NewBlock) -> Result<()> {
+        // 重启后收集器可能重发已同步的区块, last_block 只能前进
+        // After a restart the collector may emit blocks that were already synced; never move back to them.
+        // Replayed blocks all precede the synced head, so they are processed all the same.
+        if event.number.as_u64() <= self.last_block && !self.replay {
+            info!("skipping block {}, already synced up to block {}", event.number, self.last_block);
+            return Ok(());
+        }
         info!("processing new block {}", event.number);
         // Find new pools tthat were created in the last block.
         let new_pools = self
@@ -190,9 +306,70 @@ impl<M: Middleware + 'static> OpenseaSudoArb<M> {
             .get_quotes_for_pools([new_pools, touched_pools].concat())
             .await?;
         self.update_internal_pool_state(quotes);
+        self.last_block = self.last_block.max(event.number.as_u64());
+        Ok(())
+    }
+
+    /// Process reorg events, rolling back pool state derived from orphaned
+    /// blocks. Quotes are always taken at the chain head, so re-quoting every
+    /// tracked pool undoes the orphaned updates, and drops pools that were
+    /// only created on the orphaned branch. The new branch's blocks follow as
+    /// new block events.
+    async fn process_reorg_event(&mut self, event: Reorg) -> Result<()> {
+        info!("processing reorg of depth {}", event.depth);
+        let pool_addresses = self.pool_bids.keys().copied().collect::<Vec<_>>();
+        self.replace_pool_state(&pool_addresses).await?;
+        if let Some(first) = event.new_blocks.first() {
+            self.last_block = first.number.as_u64().saturating_sub(1);
+        }
         Ok(())
     }
 
+    /// Process a disconnection of the OpenSea stream. Listings posted in the
+    /// meantime were missed and cannot be replayed, so the gap is logged and
+    /// kept in the state summary for the operator.
+    fn process_stream_gap(&mut self, gap: StreamGap) {
+        let duration = gap.to.duration_since(gap.from).unwrap_or_default();
+        warn!("opensea stream was disconnected for {:?}, listings may have been missed", duration);
+        self.last_stream_gap = Some(gap);
+    }
+
+    /// Process the outcome of an arb tx, remembering orders filled by an arb
+    /// included in a block and backing off from pools whose arbs keep failing.
+    fn process_execution_report(&mut self, report: ExecutionReport<Action>) {
+        let Action::SubmitTx(arb) = report.action;
+        match report.result {
+            // 交易仍未上链 (例如等待回执超时), 既不算成交也不算失败
+            // Still pending, e.g. the receipt timed out: neither filled nor failed.
+            Ok(execution) if execution.block_number.is_none() => {
+                info!("arb for order {:?} is still pending in {:?}", arb.order_hash, execution.tx_hash);
+            }
+            Ok(execution) if !execution.reverted => {
+                info!("arb for order {:?} landed in {:?}", arb.order_hash, execution.tx_hash);
+                self.filled_orders.insert(arb.order_hash);
+                self.pool_failures.remove(&arb.sudo_pool);
+            }
+            result => {
+                info!("arb for order {:?} failed: {:?}", arb.order_hash, result);
+                let failures = self.pool_failures.entry(arb.sudo_pool).or_insert(0);
+                *failures += 1;
+                if *failures >= MAX_POOL_FAILURES {
+                    info!("backing off from pool {:?} after {} failures", arb.sudo_pool, failures);
+                    self.pool_backoff_until
+                        .insert(arb.sudo_pool, self.last_block + POOL_BACKOFF_BLOCKS);
+                    self.pool_failures.remove(&arb.sudo_pool);
+                }
+            }
+        }
+    }
+
+    /// Whether a pool is backed off after repeated failures.
+    fn is_backed_off(&self, pool: &H160) -> bool {
+        self.pool_backoff_until
+            .get(pool)
+            .is_some_and(|until| *until > self.last_block)
+    }
+
     /// Build arb tx from order hash and sudo pool params.
     /// 从 order hash 和 sudo pool 参数， 构建 arb 套利交易 (arb tx) 
     async fn build_arb_tx(&self, order_hash: H256, sudo_pool: H160, sudo_bid: U256 ) -> Option<Action> {
@@ -225,15 +402,31 @@ impl<M: Middleware + 'static> OpenseaSudoArb<M> {
             )
             .tx;
 
-        Some(Action::SubmitTx(SubmitTxToMempool {
-            tx,
-            gas_bid_info: Some(GasBidInfo {
-                total_profit,
-                bid_percentage: self.bid_percentage,
-            }),
+        Some(Action::SubmitTx(ArbTx {
+            order_hash,
+            sudo_pool,
+            tx: SubmitTxToMempool {
+                tx,
+                gas_bid_info: Some(GasBidInfo {
+                    total_profit,
+                    bid_percentage: self.bid_percentage,
+                }),
+            },
         }))
     }
 
+    /// Get current bids for the given pools, and only then replace the pool state.
+    async fn replace_pool_state(&mut self, pool_addresses: &[H160]) -> Result<()> {
+        let mut quotes = vec![];
+        for addresses in pool_addresses.chunks(200) {                               // 每次处理 200 个 Sudo pool 地址
+            quotes.extend(self.get_quotes_for_pools(addresses.to_vec()).await?);    // 获取这些 Sudo pool 的报价
+        }
+        self.sudo_pools.clear();
+        self.pool_bids.clear();
+        self.update_internal_pool_state(quotes);
+        Ok(())
+    }
+
     /// Get quotes for a list of pools.
     async fn get_quotes_for_pools(&self, pools: Vec<H160>) -> Result<Vec<(H160, SellQuote)>> {
         let quotes = self.quoter.get_multiple_sell_quotes(pools.clone()).await?;
@@ -279,17 +472,17 @@ impl<M: Middleware + 'static> OpenseaSudoArb<M> {
         Ok(touched_pools)
     }
 
-    /// Find all pools that were created in a given block range.
+    /// Find all pools that were created in a given block range, both ends included.
     async fn get_new_pools(&self, from_block: u64, to_block: u64) -> Result<Vec<H160>> {
         let mut pool_addresses = vec![];
 
         // Maxium range for a single Alchemy query is 2000 blocks.
-        for block in (from_block..to_block).step_by(2000) {
+        for (start, end) in block_ranges(from_block, to_block, 2000) {
             let events = self
                 .lssvm_pair_factory
                 .event::<NewPairFilter>()
-                .from_block(block)
-                .to_block(block + 2000)
+                .from_block(start)
+                .to_block(end)
                 .query()
                 .await?;
 
@@ -301,10 +494,40 @@ impl<M: Middleware + 'static> OpenseaSudoArb<M> {
             info!(
                 "found {} new pools in block range, total progress: {}%",
                 addresses.len(),
-                100 * (block - from_block) / (to_block - from_block)
+                100 * (end - from_block + 1) / (to_block - from_block + 1)
             );
             pool_addresses.extend(addresses);
         }
         Ok(pool_addresses)
     }
 }
+
+// 把区块范围 (包含两端) 切分成最多 size 个区块的查询范围
+/// Splits the blocks from `from_block` to `to_block`, both included, into
+/// ranges of at most `size` blocks, each given by its first and last block.
+fn block_ranges(from_block: u64, to_block: u64, size: u64) -> impl Iterator<Item = (u64, u64)> {
+    (from_block..=to_block)
+        .step_by(size as usize)
+        .map(move |start| (start, to_block.min(start + size - 1)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::block_ranges;
+
+    /// Test that catching up a single block queries that block.
+    #[test]
+    fn test_block_ranges_include_single_block() {
+        assert_eq!(block_ranges(7, 7, 2000).collect::<Vec<_>>(), vec![(7, 7)]);
+    }
+
+    /// Test that ranges cover every block once, including the last one.
+    #[test]
+    fn test_block_ranges_cover_both_ends() {
+        assert_eq!(
+            block_ranges(1, 4500, 2000).collect::<Vec<_>>(),
+            vec![(1, 2000), (2001, 4000), (4001, 4500)]
+        );
+        assert_eq!(block_ranges(8, 7, 2000).count(), 0);
+    }
+}
@@ -1,6 +1,10 @@
 use arbot_core::{
-    collectors::{block_collector::NewBlock, opensea_order_collector::OpenseaOrder},
+    collectors::{
+        block_collector::{NewBlock, Reorg},
+        opensea_order_collector::OpenseaEvent,
+    },
     executors::mempool_executor::SubmitTxToMempool,
+    types::ExecutionReport,
 };
 
 use bindings::zone_interface::{
@@ -8,10 +12,15 @@ use bindings::zone_interface::{
     BasicOrderParameters
 };
 
+use serde::{Deserialize, Serialize};
+
+use std::collections::HashMap;
+
 use ethers::types::{
     Chain, 
     H160, 
-    H256
+    H256,
+    U256,
 };
 
 use opensea_v2::types::{
@@ -23,16 +32,34 @@ use opensea_v2::types::{
 };
 
 /// Core Event enum for the current strategy.                           // 当前策略的 核心事件枚举
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum Event {
-    NewBlock(NewBlock),                                                 // 新区块 事件
-    OpenseaOrder(Box<OpenseaOrder>),                                    // opensea 挂单 事件
+    NewBlock(Box<NewBlock>),                                            // 新区块 事件
+    Reorg(Reorg),                                                       // 链重组 事件
+    Opensea(OpenseaEvent),                                              // opensea 挂单 与 断流 事件
+    ExecutionReport(Box<ExecutionReport<Action>>),                      // 执行结果 事件
 }
 
 /// Core Action enum for the current strategy.                          // 当前策略的 核心动作枚举
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum Action {
-    SubmitTx(SubmitTxToMempool),                                        // 提交交易
+    SubmitTx(ArbTx),                                                    // 提交交易
+}
+
+/// An arb transaction, with the order and pool it trades against.      // 套利交易, 以及对应的订单和池子
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ArbTx {
+    pub order_hash: H256,                                               // opensea 订单哈希
+    pub sudo_pool: H160,                                                // sudo 池子地址
+    pub tx: SubmitTxToMempool,                                          // 交易
+}
+
+/// Pool state persisted across restarts.                              // 重启之间保存的池子状态
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PoolSnapshot {
+    pub sudo_pools: HashMap<H160, Vec<H160>>,                           // NFT 地址 -> Sudo pool 地址列表
+    pub pool_bids: HashMap<H160, U256>,                                 // Sudo pool 地址 -> 当前出价
+    pub last_block: u64,                                                // 最后同步的区块
 }
 
 ///  我们需要传递给策略的 配置变量
@@ -40,6 +67,7 @@ pub enum Action {
 pub struct Config {
     pub arb_contract_address: H160,                                     // 套利合约 地址
     pub bid_percentage: u64,                                            // 利润的百分比
+    pub replay: bool,                                                   // 回放记录的事件
 }
 
 /// 将哈希转换为 fulfill listing 请求 的 函数